@@ -0,0 +1,275 @@
+use crate::cache;
+use crate::config::Config;
+use crate::git;
+use crate::lib_table::{LibTable, LibTableRow};
+use crate::libraries::{get_libraries, save_libraries, Libraries, Library};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{env, error};
+
+/// Match unmanaged lib-table rows against the library index and record the matches in
+/// installed.ron, so they can be updated and uninstalled like installed libraries.
+pub fn adopt(config: Config, dry_run: bool) -> Result<(), Box<dyn error::Error>> {
+    let index = get_libraries(config.libraries.clone())?;
+    let installed_path = format!("{}/.config/kibrarian/installed.ron", env!("HOME"));
+    let mut installed_libraries = get_libraries(installed_path.clone())?;
+    let sym_lib_table = LibTable::load(&config.sym_lib_table, "sym_lib_table")?;
+    let fp_lib_table = LibTable::load(&config.fp_lib_table, "fp_lib_table")?;
+
+    let mut adopted: HashMap<String, Library> = HashMap::new();
+    let mut unmatched = Vec::new();
+    let mut suggested = Vec::new();
+
+    for (table, table_name) in [
+        (&sym_lib_table, "sym-lib-table"),
+        (&fp_lib_table, "fp-lib-table"),
+    ]
+    .iter()
+    {
+        for row in table.rows.iter() {
+            if is_managed(row, &installed_libraries) {
+                continue;
+            }
+
+            let name = match match_row(row, &index) {
+                Some(Match::Library(x)) => x,
+                Some(Match::Candidate(x)) => {
+                    suggested.push(format!(
+                        "{} row {} ({}) -> [{}]?",
+                        table_name, row.name, row.uri, x
+                    ));
+                    continue;
+                }
+                None => {
+                    unmatched.push(format!("{} row {} ({})", table_name, row.name, row.uri));
+                    continue;
+                }
+            };
+
+            if installed_libraries.lib_map.contains_key(&name) {
+                println!(
+                    "{} row {} matches already installed library {}, skipping.",
+                    table_name, row.name, name
+                );
+                continue;
+            }
+
+            println!("{} row {} -> [{}]", table_name, row.name, name);
+            let library = adopted.entry(name.clone()).or_insert_with(|| {
+                let mut library = index.lib_map[&name].clone();
+                library.adopted = true;
+                library
+            });
+            if *table_name == "sym-lib-table" {
+                library.sym_lib_rows.push(row.clone());
+            } else {
+                library.fp_lib_rows.push(row.clone());
+            }
+        }
+    }
+
+    if !unmatched.is_empty() {
+        println!("No library found for:");
+        for row in unmatched.iter() {
+            println!("  {}", row);
+        }
+    }
+
+    // names alone don't prove the files are the library's, install it to replace them
+    if !suggested.is_empty() {
+        println!("Not adopted, only the names of these rows match a library:");
+        for row in suggested.iter() {
+            println!("  {}", row);
+        }
+    }
+
+    if adopted.is_empty() {
+        println!("No libraries adopted.");
+        return Ok(());
+    }
+
+    if dry_run {
+        println!(
+            "Would add {} adopted libraries to installed.ron.",
+            adopted.len()
+        );
+        return Ok(());
+    }
+
+    println!(
+        "Adding {} adopted libraries to installed.ron...",
+        adopted.len()
+    );
+    installed_libraries.lib_map.extend(adopted);
+    save_libraries(&installed_libraries, installed_path)?;
+
+    Ok(())
+}
+
+fn is_managed(row: &LibTableRow, installed_libraries: &Libraries) -> bool {
+    installed_libraries
+        .lib_map
+        .values()
+        .any(|library| library.sym_lib_rows.contains(row) || library.fp_lib_rows.contains(row))
+}
+
+// index library a lib-table row matches
+enum Match {
+    // the files of the row are in the repository or local source of the library, or files
+    // of the same name are
+    Library(String),
+    // only the names of the row or its files match the library
+    Candidate(String),
+}
+
+// find the index library a row belongs to by git remote or uri, or a candidate by file names
+fn match_row(row: &LibTableRow, index: &Libraries) -> Option<Match> {
+    let path = expand_uri(&row.uri);
+    let stem = path
+        .file_stem()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut libraries: Vec<&Library> = index.lib_map.values().collect();
+    libraries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    // the git remote of the repository containing the library files
+    let directory = if path.is_dir() {
+        Some(path.as_path())
+    } else {
+        path.parent()
+    };
+    if let Some(remote) = directory.and_then(git::remote_url) {
+        let remote = normalize_url(&remote);
+        if let Some(library) = libraries
+            .iter()
+            .find(|library| normalize_url(&library.url) == remote)
+        {
+            return Some(Match::Library(library.name.clone()));
+        }
+    }
+
+    // the uri lies inside a local library source
+    if let Some(library) = libraries.iter().find(|library| match library.local_path() {
+        Some(local_path) => path.starts_with(local_path),
+        None => false,
+    }) {
+        return Some(Match::Library(library.name.clone()));
+    }
+
+    // a library whose clone or local source provides a file of the same name
+    if let Some(library) = libraries.iter().find(|library| provides(library, &stem)) {
+        return Some(Match::Library(library.name.clone()));
+    }
+
+    // the file name is mapped to a nickname, or a directory is named after the library
+    libraries
+        .iter()
+        .find(|library| {
+            library.nicknames.contains_key(&stem)
+                || library.nicknames.values().any(|x| *x == row.name)
+                || path
+                    .components()
+                    .any(|x| x.as_os_str().to_string_lossy() == library.name)
+        })
+        .map(|library| Match::Candidate(library.name.clone()))
+}
+
+// whether the local source or the installed or cached clone of a library has a library
+// file with the given stem
+fn provides(library: &Library, stem: &str) -> bool {
+    let root = match library
+        .local_path()
+        .or_else(|| cache::clone_path(&library.name))
+    {
+        Some(x) => x,
+        None => return false,
+    };
+    match library.provided_files(&root) {
+        Ok((symbols, footprints)) => symbols.iter().chain(footprints.iter()).any(|(path, _)| {
+            path.file_stem()
+                .is_some_and(|x| x.to_string_lossy() == stem)
+        }),
+        Err(_) => false,
+    }
+}
+
+// expand ${VAR}, $(VAR) and ~ in a lib-table uri
+fn expand_uri(uri: &str) -> PathBuf {
+    let mut expanded = String::new();
+    let mut rest = uri;
+
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let close = match after.chars().next() {
+            Some('{') => '}',
+            Some('(') => ')',
+            _ => {
+                expanded.push('$');
+                rest = after;
+                continue;
+            }
+        };
+        match after.find(close) {
+            Some(end) => {
+                let name = &after[1..end];
+                expanded.push_str(&env::var(name).unwrap_or_default());
+                rest = &after[end + 1..];
+            }
+            None => {
+                expanded.push('$');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+
+    match expanded.strip_prefix("~/") {
+        Some(x) => Path::new(env!("HOME")).join(x),
+        None => PathBuf::from(expanded),
+    }
+}
+
+// reduce https, ssh and scp-like git urls to host/path for comparison
+pub fn normalize_url(url: &str) -> String {
+    let mut url = url.trim().trim_end_matches('/');
+    url = url.strip_suffix(".git").unwrap_or(url);
+    if let Some(index) = url.find("://") {
+        url = &url[index + 3..];
+    }
+    if let Some(index) = url.find('@') {
+        url = &url[index + 1..];
+    }
+    url.replacen(':', "/", 1).to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_url;
+
+    #[test]
+    fn normalize_url_ignores_scheme_user_and_suffix() {
+        let expected = "github.com/kicad/kicad-symbols";
+        for url in [
+            "https://github.com/KiCad/kicad-symbols",
+            "https://github.com/KiCad/kicad-symbols.git",
+            "https://github.com/KiCad/kicad-symbols/",
+            "ssh://git@github.com/KiCad/kicad-symbols.git",
+            "git@github.com:KiCad/kicad-symbols.git",
+            " git://github.com/KiCad/kicad-symbols ",
+        ]
+        .iter()
+        {
+            assert_eq!(normalize_url(url), expected, "{}", url);
+        }
+    }
+
+    #[test]
+    fn normalize_url_distinguishes_repositories() {
+        assert_ne!(
+            normalize_url("https://github.com/a/lib"),
+            normalize_url("https://github.com/b/lib")
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use crate::checksum::sha256;
+use flate2::read::GzDecoder;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::{error, fmt, fs};
+
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+pub enum ArchiveError {
+    ChecksumError(String, String),
+    UnverifiedError(String),
+    UnknownFormatError,
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveError::ChecksumError(expected, actual) => write!(
+                f,
+                "Archive checksum mismatch, expected {} but got {}.",
+                expected, actual
+            ),
+            ArchiveError::UnverifiedError(actual) => write!(
+                f,
+                "Archive has no sha256 in the library index, got {}. Add it, or set unverified_archives in config.ron to install archives without one.",
+                actual
+            ),
+            ArchiveError::UnknownFormatError => {
+                write!(f, "Archive is not a zip, tar.gz or tar file.")
+            }
+        }
+    }
+}
+
+impl error::Error for ArchiveError {
+    fn description(&self) -> &str {
+        match self {
+            ArchiveError::ChecksumError(_, _) => "Archive checksum mismatch.",
+            ArchiveError::UnverifiedError(_) => "Archive has no sha256 in the library index.",
+            ArchiveError::UnknownFormatError => "Archive is not a zip, tar.gz or tar file.",
+        }
+    }
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+pub fn download(url: &str) -> Result<Vec<u8>, Box<dyn error::Error>> {
+    println!("downloading from: {}", url);
+
+    let mut data = Vec::new();
+    ureq::get(url)
+        .call()?
+        .into_reader()
+        .read_to_end(&mut data)?;
+    println!("received {} kb", data.len() / 1024);
+
+    Ok(data)
+}
+
+/// Check the SHA-256 of an archive against the expected hex digest. An archive without one
+/// is only accepted when unverified archives are allowed.
+pub fn verify(data: &[u8], expected: Option<&str>, unverified: bool) -> Result<(), ArchiveError> {
+    let actual = sha256(data);
+    match expected {
+        Some(expected) if !expected.eq_ignore_ascii_case(&actual) => {
+            Err(ArchiveError::ChecksumError(expected.to_owned(), actual))
+        }
+        Some(_) => Ok(()),
+        None if unverified => {
+            println!("No sha256 given for archive, got: {}", actual);
+            Ok(())
+        }
+        None => Err(ArchiveError::UnverifiedError(actual)),
+    }
+}
+
+/// Extract a zip, tar.gz or tar archive into destination.
+///
+/// A single top level directory, as most release archives have, is stripped.
+pub fn extract(data: &[u8], destination: &str) -> Result<(), Box<dyn error::Error>> {
+    println!("extracting to: {}", destination);
+
+    if data.starts_with(b"PK\x03\x04") {
+        zip::ZipArchive::new(Cursor::new(data))?.extract(destination)?;
+    } else if data.starts_with(&[0x1f, 0x8b]) {
+        tar::Archive::new(GzDecoder::new(data)).unpack(destination)?;
+    } else if data.len() > 262 && &data[257..262] == b"ustar" {
+        tar::Archive::new(data).unpack(destination)?;
+    } else {
+        return Err(Box::new(ArchiveError::UnknownFormatError));
+    }
+
+    strip_top_level(Path::new(destination))?;
+
+    Ok(())
+}
+
+// move the contents of a lone top level directory up into destination
+fn strip_top_level(destination: &Path) -> Result<(), Box<dyn error::Error>> {
+    let entries = fs::read_dir(destination)?
+        .map(|res| res.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if entries.len() != 1 || !entries[0].is_dir() {
+        return Ok(());
+    }
+
+    // the directory moves next to destination first, it may contain an entry of its own name
+    let top_level = destination.with_file_name(format!(
+        ".{}.top-level",
+        destination
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+    ));
+    fs::rename(&entries[0], &top_level)?;
+    for entry in fs::read_dir(&top_level)? {
+        let entry = entry?;
+        fs::rename(entry.path(), destination.join(entry.file_name()))?;
+    }
+    fs::remove_dir(&top_level)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip_top_level, verify};
+    use std::{env, fs, process};
+
+    #[test]
+    fn verify_requires_a_checksum() {
+        let digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert!(verify(b"abc", Some(digest), false).is_ok());
+        assert!(verify(b"abd", Some(digest), true).is_err());
+        assert!(verify(b"abc", None, false).is_err());
+        assert!(verify(b"abc", None, true).is_ok());
+    }
+
+    #[test]
+    fn strip_top_level_named_like_its_child() {
+        let destination = env::temp_dir().join(format!("kibrarian-test-strip-{}", process::id()));
+        fs::create_dir_all(destination.join("lib/lib")).unwrap();
+        fs::write(destination.join("lib/lib/a.lib"), "a").unwrap();
+        fs::write(destination.join("lib/README"), "b").unwrap();
+
+        strip_top_level(&destination).unwrap();
+        assert_eq!(
+            fs::read_to_string(destination.join("lib/a.lib")).unwrap(),
+            "a"
+        );
+        assert_eq!(fs::read_to_string(destination.join("README")).unwrap(), "b");
+        fs::remove_dir_all(&destination).unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+use crate::config::Config;
+use crate::dependencies;
+use crate::libraries::{
+    get_libraries, install, plan, uninstall, InstallOptions, Libraries, Library, LibraryError,
+    UninstallOptions,
+};
+use crate::staging::{self, Staging};
+use std::{error, fs, io};
+
+/// Library names listed in a file, one per line, ignoring blank lines and # comments.
+pub fn read_targets(path: &str) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_owned())
+        .collect())
+}
+
+/// Outcome of a batch, printed once every target was tried.
+pub struct Summary {
+    action: &'static str,
+    pub succeeded: Vec<String>,
+    pub failed: Vec<(String, String)>,
+}
+
+impl Summary {
+    pub fn new(action: &'static str) -> Summary {
+        Summary {
+            action,
+            succeeded: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, target: &str, result: Result<(), Box<dyn error::Error>>) {
+        match result {
+            Ok(()) => self.succeeded.push(target.to_owned()),
+            Err(e) => {
+                println!("{}", e);
+                self.failed.push((target.to_owned(), e.to_string()));
+            }
+        }
+    }
+
+    /// Print the summary, returns whether every target succeeded.
+    pub fn print(&self) -> bool {
+        println!();
+        if !self.succeeded.is_empty() {
+            println!("{}: {}", self.action, self.succeeded.join(", "));
+        }
+        if !self.failed.is_empty() {
+            println!("Failed:");
+            for (target, reason) in self.failed.iter() {
+                println!("  {}: {}", target, reason);
+            }
+        }
+        self.failed.is_empty()
+    }
+}
+
+// split a target into the library name and an optional version requirement, as in foo@^2.1
+fn split_target(target: &str) -> (&str, Option<&str>) {
+    match target.find('@') {
+        Some(index) => (&target[..index], Some(&target[index + 1..])),
+        None => (target, None),
+    }
+}
+
+// drop repeated targets, keeping the first
+fn dedup(targets: Vec<String>) -> Vec<String> {
+    let mut unique: Vec<String> = Vec::new();
+    for target in targets {
+        if !unique.contains(&target) {
+            unique.push(target);
+        }
+    }
+    unique
+}
+
+/// Install every target, reporting the plan first and a summary of successes and failures
+/// at the end. Returns whether every target was installed.
+pub fn install_all(
+    config: Config,
+    global: bool,
+    targets: Vec<String>,
+    options: &InstallOptions,
+) -> Result<bool, Box<dyn error::Error>> {
+    let index = get_libraries(config.libraries.clone())?;
+    let installed_libraries =
+        get_libraries(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))?;
+    let mut summary = Summary::new("Installed");
+
+    // check every target against the index and installed libraries before installing anything
+    println!("Plan:");
+    let mut planned = Vec::new();
+    for target in dedup(targets) {
+        let (name, version) = split_target(&target);
+        let problem = if !index.lib_map.contains_key(name) {
+            Some(LibraryError::LibraryNotFoundError)
+        } else if installed_libraries
+            .lib_map
+            .get(name)
+            .is_some_and(|x| !x.dependency)
+        {
+            Some(LibraryError::LibraryInstalledError)
+        } else if let Some(Err(e)) = version.map(|x| dependencies::parse_requirement(name, x)) {
+            Some(e)
+        } else {
+            None
+        };
+
+        match problem {
+            Some(e) => {
+                println!("  skip {}: {}", target, e);
+                summary.failed.push((target.clone(), e.to_string()));
+            }
+            None => {
+                println!("  install {}", target);
+                planned.push(target);
+            }
+        }
+    }
+
+    // clone every planned library concurrently, resolution errors are reported when each
+    // target is installed
+    let _staging = Staging::begin()?;
+    let mut libraries: Vec<Library> = Vec::new();
+    for target in planned.iter() {
+        let (name, version) = split_target(target);
+        let mut options = options.clone();
+        if let Some(version) = version {
+            options.version(version);
+        }
+        if let Ok(plan) = plan(&index, &installed_libraries, name, &options, &config) {
+            for library in plan {
+                if !libraries.iter().any(|x| x.name == library.name) {
+                    libraries.push(library);
+                }
+            }
+        }
+    }
+    if !options.is_dry_run() {
+        staging::stage(&config, &libraries);
+    }
+
+    for target in planned.iter() {
+        println!("Installing {}...", target);
+        let (name, version) = split_target(target);
+        let mut options = options.clone();
+        if let Some(version) = version {
+            options.version(version);
+        }
+        summary.record(target, install(config.clone(), global, name, &options));
+    }
+
+    Ok(summary.print())
+}
+
+/// Uninstall every target, libraries depending on others in the batch first, reporting the
+/// plan first and a summary of successes and failures at the end. Returns whether every
+/// target was uninstalled.
+pub fn uninstall_all(
+    config: Config,
+    global: bool,
+    targets: Vec<String>,
+    options: &UninstallOptions,
+) -> Result<bool, Box<dyn error::Error>> {
+    let installed_libraries =
+        get_libraries(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))?;
+    let mut summary = Summary::new("Uninstalled");
+
+    println!("Plan:");
+    let mut remaining = Vec::new();
+    for target in dedup(targets) {
+        if installed_libraries.lib_map.contains_key(&target) {
+            remaining.push(target);
+        } else {
+            let e = LibraryError::LibraryNotInstalledError;
+            println!("  skip {}: {}", target, e);
+            summary.failed.push((target, e.to_string()));
+        }
+    }
+
+    // uninstall libraries before the libraries in the batch they depend on, libraries
+    // still required from outside the batch are left to uninstall and --force
+    let mut planned = Vec::new();
+    while !remaining.is_empty() {
+        let next = remaining
+            .iter()
+            .position(|target| required_by(&installed_libraries, target, &planned).is_empty());
+        match next {
+            Some(index) => {
+                let target = remaining.remove(index);
+                println!("  uninstall {}", target);
+                planned.push(target);
+            }
+            None => {
+                for target in remaining.iter() {
+                    println!(
+                        "  uninstall {} (required by {})",
+                        target,
+                        required_by(&installed_libraries, target, &planned).join(", ")
+                    );
+                }
+                planned.append(&mut remaining);
+            }
+        }
+    }
+
+    // a dry run removes the targets from an in-memory copy of installed.ron as it goes
+    let mut options = options.clone();
+    for target in planned.iter() {
+        println!("Uninstalling {}...", target);
+        let result = uninstall(config.clone(), global, target, &options);
+        if result.is_ok() {
+            options.removed(target);
+        }
+        summary.record(target, result);
+    }
+
+    Ok(summary.print())
+}
+
+// installed libraries depending on name that are not already planned for removal
+fn required_by(installed_libraries: &Libraries, name: &str, planned: &[String]) -> Vec<String> {
+    dependencies::dependents(installed_libraries, name)
+        .into_iter()
+        .filter(|x| !planned.contains(x))
+        .collect()
+}
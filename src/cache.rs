@@ -0,0 +1,304 @@
+use crate::git::{self, CloneOptions};
+use crate::libraries::{get_libraries, installed_directories, remove_dir, Library, Source};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{error, fs, io};
+
+fn cache_path() -> PathBuf {
+    PathBuf::from(format!("{}/.kibrarian/cache", env!("HOME")))
+}
+
+/// Installation of a library, a clone for remote git libraries.
+pub fn installation_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("{}/.kibrarian/extra/{}", env!("HOME"), name))
+}
+
+/// Clone of a remote git library kept in the cache after it was uninstalled.
+pub fn cached_path(name: &str) -> PathBuf {
+    cache_path().join(name)
+}
+
+/// Last archive downloaded for a library.
+pub fn archive_path(name: &str) -> PathBuf {
+    cache_path().join(format!("{}.archive", name))
+}
+
+/// Existing clone of a library, in its installation or in the cache.
+pub fn clone_path(name: &str) -> Option<PathBuf> {
+    vec![installation_path(name), cached_path(name)]
+        .into_iter()
+        .find(|path| path.join(".git").is_dir())
+}
+
+/// Whether a library can be installed offline, local libraries always can.
+pub fn contains(library: &Library) -> bool {
+    match (library.source, library.local_path()) {
+        (_, Some(_)) => true,
+        (Source::Archive, None) => archive_path(&library.name).is_file(),
+        _ => clone_path(&library.name).is_some(),
+    }
+}
+
+/// Whether the installation of a library is a clone of a remote git library that keep moves
+/// into the cache.
+pub fn keeps(library: &Library) -> bool {
+    library.source == Source::Git
+        && library.local_path().is_none()
+        && installation_path(&library.name).join(".git").is_dir()
+}
+
+/// Move the clone of a remote git library from its installation into the cache, replacing
+/// a previously cached clone. Returns whether there was a clone to keep.
+pub fn keep(library: &Library) -> io::Result<bool> {
+    if !keeps(library) {
+        return Ok(false);
+    }
+
+    remove_dir(cached_path(&library.name))?;
+    fs::create_dir_all(cache_path())?;
+    fs::rename(installation_path(&library.name), cached_path(&library.name))?;
+    Ok(true)
+}
+
+/// Move the cached clone of a library to destination and check out the revision in options,
+/// fetching it from url first unless offline. Returns false if the library has no cached
+/// clone.
+pub fn restore(
+    name: &str,
+    url: &str,
+    destination: &Path,
+    options: &CloneOptions,
+    offline: bool,
+) -> Result<bool, Box<dyn error::Error>> {
+    let cached = cached_path(name);
+    if !cached.join(".git").is_dir() {
+        return Ok(false);
+    }
+
+    fs::rename(&cached, destination)?;
+    if let Err(e) = git::refresh(destination, url, options, offline) {
+        // leave the clone in the cache for the next attempt
+        let _ = fs::rename(destination, &cached);
+        return Err(Box::new(e));
+    }
+    Ok(true)
+}
+
+/// Keep the data of a downloaded archive for offline installs.
+pub fn keep_archive(name: &str, data: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(cache_path())?;
+    fs::write(archive_path(name), data)
+}
+
+// bytes used by the files below path, not following symlinks into linked working trees
+fn size(path: &Path) -> io::Result<u64> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(x) => x,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    if !metadata.is_dir() {
+        return Ok(if metadata.is_file() {
+            metadata.len()
+        } else {
+            0
+        });
+    }
+
+    let mut total = 0;
+    for entry in fs::read_dir(path)? {
+        total += size(&entry?.path())?;
+    }
+    Ok(total)
+}
+
+fn human(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+// names of the entries of a directory, an archive counts as its library
+fn entry_names(directory: &Path) -> io::Result<Vec<String>> {
+    let entries = match fs::read_dir(directory) {
+        Ok(x) => x,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        names.push(name.trim_end_matches(".archive").to_owned());
+    }
+    Ok(names)
+}
+
+fn installed_names() -> Result<BTreeSet<String>, Box<dyn error::Error>> {
+    let installed_libraries =
+        get_libraries(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))?;
+    Ok(installed_libraries.lib_map.keys().cloned().collect())
+}
+
+// clones in ~/.kibrarian/extra and the cache
+fn clones() -> io::Result<Vec<PathBuf>> {
+    let mut clones = Vec::new();
+    for directory in [installation_path(""), cache_path()].iter() {
+        for name in entry_names(directory)? {
+            let path = directory.join(&name);
+            if !fs::symlink_metadata(&path)?.file_type().is_symlink() && path.join(".git").is_dir()
+            {
+                clones.push(path);
+            }
+        }
+    }
+    clones.sort();
+    Ok(clones)
+}
+
+/// Print the disk space used by each library, for its clone or downloaded archive and for
+/// its installed files.
+pub fn du() -> Result<(), Box<dyn error::Error>> {
+    let installed = installed_names()?;
+    let mut names = installed.clone();
+    names.extend(entry_names(&installation_path(""))?);
+    names.extend(entry_names(&cache_path())?);
+
+    let width = names.iter().map(|x| x.len()).max().unwrap_or(0).max(7);
+    println!(
+        "{:width$}  {:>10}  {:>10}  {:>10}",
+        "library",
+        "clone",
+        "installed",
+        "total",
+        width = width
+    );
+
+    let (mut clone_total, mut installed_total) = (0, 0);
+    for name in names.iter() {
+        let clone_size = size(&installation_path(name))?
+            + size(&cached_path(name))?
+            + size(&archive_path(name))?;
+        let mut installed_size = 0;
+        for directory in installed_directories(name) {
+            installed_size += size(Path::new(&format!(
+                "{}/.kibrarian/libraries/{}",
+                env!("HOME"),
+                directory
+            )))?;
+        }
+        clone_total += clone_size;
+        installed_total += installed_size;
+
+        println!(
+            "{:width$}  {:>10}  {:>10}  {:>10}{}",
+            name,
+            human(clone_size),
+            human(installed_size),
+            human(clone_size + installed_size),
+            if installed.contains(name) {
+                ""
+            } else {
+                "  (not installed)"
+            },
+            width = width
+        );
+    }
+
+    println!(
+        "{:width$}  {:>10}  {:>10}  {:>10}",
+        "total",
+        human(clone_total),
+        human(installed_total),
+        human(clone_total + installed_total),
+        width = width
+    );
+
+    Ok(())
+}
+
+/// Remove clones and archives of libraries that are not in installed.ron, the only record of
+/// installed libraries while project installations are unimplemented.
+pub fn clean(dry_run: bool) -> Result<(), Box<dyn error::Error>> {
+    let installed = installed_names()?;
+
+    let mut freed = 0;
+    for directory in [installation_path(""), cache_path()].iter() {
+        let mut entries: Vec<PathBuf> = match fs::read_dir(directory) {
+            Ok(x) => x
+                .map(|entry| entry.map(|x| x.path()))
+                .collect::<io::Result<_>>()?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(Box::new(e)),
+        };
+        entries.sort();
+
+        for path in entries {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if installed.contains(name.trim_end_matches(".archive")) {
+                continue;
+            }
+
+            let path_size = size(&path)?;
+            freed += path_size;
+            if dry_run {
+                println!("Would remove {} ({})", path.display(), human(path_size));
+            } else {
+                println!("Removing {} ({})", path.display(), human(path_size));
+                if path.is_file() {
+                    fs::remove_file(&path)?;
+                } else {
+                    remove_dir(&path)?;
+                }
+            }
+        }
+    }
+
+    if dry_run {
+        println!("Would free {}.", human(freed));
+    } else {
+        println!("Freed {}.", human(freed));
+    }
+
+    Ok(())
+}
+
+/// Run git gc on every clone, pruning unreachable objects.
+pub fn gc() -> Result<(), Box<dyn error::Error>> {
+    let clones = clones()?;
+    if clones.is_empty() {
+        println!("No clones to collect.");
+    }
+
+    let mut freed = 0;
+    for path in clones {
+        let before = size(&path)?;
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(&path)
+            .args(["gc", "--prune=now", "--quiet"])
+            .status()
+            .map_err(|e| io::Error::new(e.kind(), format!("Couldn't run git gc: {}", e)))?;
+        let after = size(&path)?;
+
+        if status.success() {
+            println!("{}: {} -> {}", path.display(), human(before), human(after));
+        } else {
+            println!("{}: git gc failed ({})", path.display(), status);
+        }
+        freed += before.saturating_sub(after);
+    }
+    println!("Freed {}.", human(freed));
+
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+pub fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    Ok(sha256(&fs::read(path)?))
+}
+
+/// Every file below path, following symlinks, sorted.
+pub fn walk<P: AsRef<Path>>(path: P) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if !path.as_ref().exists() {
+        return Ok(files);
+    }
+
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.append(&mut walk(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// SHA-256 of every file below each of the directories, keyed by path relative to base.
+pub fn manifest(base: &Path, directories: &[String]) -> io::Result<HashMap<String, String>> {
+    let mut manifest = HashMap::new();
+    for directory in directories.iter() {
+        for file in walk(base.join(directory))? {
+            let relative = file.strip_prefix(base).unwrap_or(&file);
+            manifest.insert(relative.to_string_lossy().into_owned(), sha256_file(&file)?);
+        }
+    }
+
+    Ok(manifest)
+}
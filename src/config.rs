@@ -1,17 +1,43 @@
-use crate::git::clone;
-use crate::libraries::Libraries;
+use crate::git::{clone, CloneOptions};
+use crate::history::write_atomic;
+use crate::libraries::{InstallMode, Libraries, Selection};
 use ron::de::from_reader;
 use ron::ser;
 use serde::{Deserialize, Serialize};
-use std::io::Write;
-use std::{collections::HashMap, error, fmt, fs, fs::File, io};
+use std::{collections::HashMap, error, fmt, fs::File, io};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
     pub libraries: String,
     pub installed: String,
     pub fp_lib_table: String,
     pub sym_lib_table: String,
+    #[serde(default = "default_full_clone_fallback")]
+    pub full_clone_fallback: bool,
+    #[serde(default)]
+    pub nickname_prefixes: HashMap<String, String>,
+    #[serde(default)]
+    pub install_mode: InstallMode,
+    #[serde(default)]
+    pub selections: HashMap<String, Selection>,
+    #[serde(default = "default_jobs")]
+    pub jobs: usize,
+    #[serde(default)]
+    pub offline: bool,
+    #[serde(default)]
+    pub url_rewrites: HashMap<String, String>,
+    #[serde(default)]
+    pub projects: Vec<String>,
+    #[serde(default)]
+    pub unverified_archives: bool,
+}
+
+fn default_full_clone_fallback() -> bool {
+    true
+}
+
+fn default_jobs() -> usize {
+    4
 }
 
 impl Config {
@@ -27,6 +53,15 @@ impl Config {
                 "{}/projects/kibrarian/test/config/kicad/sym-lib-table",
                 env!("HOME")
             ),
+            full_clone_fallback: true,
+            nickname_prefixes: HashMap::new(),
+            install_mode: InstallMode::Copy,
+            selections: HashMap::new(),
+            jobs: default_jobs(),
+            offline: false,
+            url_rewrites: HashMap::new(),
+            projects: Vec::new(),
+            unverified_archives: false,
         }
     }
 
@@ -46,6 +81,39 @@ impl Config {
         self.sym_lib_table = path.to_owned();
     }
 
+    pub fn full_clone_fallback(&mut self, fallback: bool) {
+        self.full_clone_fallback = fallback;
+    }
+
+    /// Maximum number of libraries cloned at the same time.
+    pub fn jobs(&mut self, jobs: usize) {
+        self.jobs = jobs.max(1);
+    }
+
+    /// Resolve and install libraries only from the cache, without network access.
+    pub fn offline(&mut self, offline: bool) {
+        self.offline = offline;
+    }
+
+    /// Fetch urls starting with prefix from replacement instead, like git's insteadOf.
+    pub fn url_rewrite(&mut self, prefix: &str, replacement: &str) {
+        self.url_rewrites
+            .insert(prefix.to_owned(), replacement.to_owned());
+    }
+
+    /// Url with the longest matching prefix of the rewrite rules replaced.
+    pub fn rewrite_url(&self, url: &str) -> String {
+        match self
+            .url_rewrites
+            .iter()
+            .filter(|(prefix, _)| url.starts_with(&prefix[..]))
+            .max_by_key(|(prefix, _)| prefix.len())
+        {
+            Some((prefix, replacement)) => format!("{}{}", replacement, &url[prefix.len()..]),
+            None => url.to_owned(),
+        }
+    }
+
     pub fn wizard(&mut self) {
         println!("Welcome to the Kibrarian Setup Wizard!");
 
@@ -61,7 +129,7 @@ impl Config {
             .expect("Couldn't read line.");
         let len = libraries_path.trim_end_matches(&['\r', '\n'][..]).len();
         libraries_path.truncate(len);
-        if libraries_path != "" {
+        if !libraries_path.is_empty() {
             self.libraries(&libraries_path[..]);
         }
 
@@ -77,7 +145,7 @@ impl Config {
             .expect("Couldn't read line.");
         let len = installed_path.trim_end_matches(&['\r', '\n'][..]).len();
         installed_path.truncate(len);
-        if installed_path != "" {
+        if !installed_path.is_empty() {
             self.installed(&installed_path[..]);
         }
 
@@ -93,7 +161,7 @@ impl Config {
             .expect("Couldn't read line.");
         let len = fp_lib_table_path.trim_end_matches(&['\r', '\n'][..]).len();
         fp_lib_table_path.truncate(len);
-        if fp_lib_table_path != "" {
+        if !fp_lib_table_path.is_empty() {
             self.fp_lib_table(&fp_lib_table_path[..]);
         }
 
@@ -109,10 +177,37 @@ impl Config {
             .expect("Couldn't read line.");
         let len = sym_lib_table_path.trim_end_matches(&['\r', '\n'][..]).len();
         sym_lib_table_path.truncate(len);
-        if sym_lib_table_path != "" {
+        if !sym_lib_table_path.is_empty() {
             self.sym_lib_table(&sym_lib_table_path[..]);
         }
 
+        // full_clone_fallback
+        println!("Fall back to full clones when a server does not support shallow fetches?");
+        println!("Press ENTER to use default: 'y' or enter 'n' to disable.");
+        let mut full_clone_fallback = String::new();
+        io::stdin()
+            .read_line(&mut full_clone_fallback)
+            .expect("Couldn't read line.");
+        if full_clone_fallback.trim().eq_ignore_ascii_case("n") {
+            self.full_clone_fallback(false);
+        }
+
+        // url_rewrites
+        println!("Fetch repositories from mirrors?");
+        println!("Enter rules as 'prefix replacement', one per line, or press ENTER to finish.");
+        loop {
+            let mut rule = String::new();
+            io::stdin()
+                .read_line(&mut rule)
+                .expect("Couldn't read line.");
+            let mut parts = rule.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some(prefix), Some(replacement)) => self.url_rewrite(prefix, replacement),
+                (Some(_), None) => println!("Enter a prefix and its replacement."),
+                _ => break,
+            }
+        }
+
         println!("{}", self);
     }
 }
@@ -122,7 +217,24 @@ impl fmt::Display for Config {
         writeln!(f, "libraries.ron path: {}", self.libraries)?;
         writeln!(f, "installed.ron path: {}", self.installed)?;
         writeln!(f, "fp-lib-table: {}", self.fp_lib_table)?;
-        write!(f, "sym-lib-table: {}", self.sym_lib_table)
+        writeln!(f, "sym-lib-table: {}", self.sym_lib_table)?;
+        writeln!(f, "full clone fallback: {}", self.full_clone_fallback)?;
+        writeln!(f, "parallel jobs: {}", self.jobs)?;
+        writeln!(f, "offline: {}", self.offline)?;
+        write!(f, "install mode: {}", self.install_mode)?;
+        for (library, selection) in self.selections.iter() {
+            write!(f, "\nselection for {}: {}", library, selection)?;
+        }
+        for (library, prefix) in self.nickname_prefixes.iter() {
+            write!(f, "\nnickname prefix for {}: {}", library, prefix)?;
+        }
+        for project in self.projects.iter() {
+            write!(f, "\nproject: {}", project)?;
+        }
+        for (prefix, replacement) in self.url_rewrites.iter() {
+            write!(f, "\nurl rewrite: {} -> {}", prefix, replacement)?;
+        }
+        Ok(())
     }
 }
 
@@ -139,32 +251,23 @@ pub fn setup(config_file: Option<Config>) -> Result<(), Box<dyn error::Error>> {
 
         // write to file
         let serialized = ser::to_string(&new_config)?;
-
-        let mut new_config_file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(format!("{}/.config/kibrarian/config.ron", env!("HOME")))?;
-
-        let _ = new_config_file.write(serialized.as_bytes())?;
+        write_atomic(
+            format!("{}/.config/kibrarian/config.ron", env!("HOME")),
+            serialized.as_bytes(),
+        )?;
 
         // initialize installed.ron file
         println!("Initializing installed.ron...");
         let new_installed = Libraries::new();
         let serialized = ser::to_string(&new_installed)?;
-        let mut new_installed_file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(new_config.installed)?;
-
-        let _ = new_installed_file.write(serialized.as_bytes())?;
+        write_atomic(&new_config.installed, serialized.as_bytes())?;
 
         // clone libraries.ron
         println!("Cloning libraries.ron");
         clone(
-            "https://github.com/cdsupina/kibrarian-libraries.git",
+            &new_config.rewrite_url("https://github.com/cdsupina/kibrarian-libraries.git"),
             format!("{}/.config/kibrarian/sources", env!("HOME")),
+            &CloneOptions::new(),
         )?;
     }
 
@@ -188,3 +291,28 @@ pub fn load(config_path: &str) -> Option<Config> {
 
     config
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    #[test]
+    fn rewrite_url_uses_the_longest_prefix() {
+        let mut config = Config::new();
+        config.url_rewrite("https://github.com/", "https://mirror.example/github/");
+        config.url_rewrite("https://github.com/KiCad/", "file:///srv/kicad/");
+
+        assert_eq!(
+            config.rewrite_url("https://github.com/KiCad/kicad-symbols"),
+            "file:///srv/kicad/kicad-symbols"
+        );
+        assert_eq!(
+            config.rewrite_url("https://github.com/someone/lib.git"),
+            "https://mirror.example/github/someone/lib.git"
+        );
+        assert_eq!(
+            config.rewrite_url("https://gitlab.com/someone/lib"),
+            "https://gitlab.com/someone/lib"
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use crate::config::Config;
+use crate::libraries::{
+    get_libraries, uninstall, Libraries, Library, LibraryError, UninstallOptions,
+};
+use semver::{Version, VersionReq};
+use std::collections::HashMap;
+use std::error;
+
+/// Libraries needed to install query with the version requirements placed on each, every
+/// library after the libraries it depends on and query last. Installed dependencies must
+/// already meet the requirements.
+pub fn resolve(
+    index: &Libraries,
+    installed_libraries: &Libraries,
+    query: &str,
+    requirement: Option<VersionReq>,
+) -> Result<Vec<(String, Vec<VersionReq>)>, LibraryError> {
+    if !index.lib_map.contains_key(query) {
+        return Err(LibraryError::LibraryNotFoundError);
+    }
+
+    let mut requirements: HashMap<String, Vec<VersionReq>> = HashMap::new();
+    requirements
+        .entry(query.to_owned())
+        .or_default()
+        .extend(requirement);
+
+    let mut order = Vec::new();
+    visit(
+        index,
+        installed_libraries,
+        query,
+        &mut Vec::new(),
+        &mut order,
+        &mut requirements,
+    )?;
+
+    // installed libraries keep their requirements on libraries being reinstalled
+    for library in installed_libraries.lib_map.values() {
+        for dependency in library.dependencies.iter() {
+            if let Some(version) = &dependency.version {
+                if dependency.name == query
+                    || order.contains(&dependency.name)
+                        && !installed_libraries.lib_map.contains_key(&dependency.name)
+                {
+                    requirements
+                        .entry(dependency.name.clone())
+                        .or_default()
+                        .push(parse_requirement(&library.name, version)?);
+                }
+            }
+        }
+    }
+
+    Ok(order
+        .into_iter()
+        .map(|name| {
+            let library_requirements = requirements.remove(&name).unwrap_or_default();
+            (name, library_requirements)
+        })
+        .collect())
+}
+
+fn visit(
+    index: &Libraries,
+    installed_libraries: &Libraries,
+    name: &str,
+    stack: &mut Vec<String>,
+    order: &mut Vec<String>,
+    requirements: &mut HashMap<String, Vec<VersionReq>>,
+) -> Result<(), LibraryError> {
+    if order.iter().any(|x| x == name) {
+        return Ok(());
+    }
+    if stack.iter().any(|x| x == name) {
+        return Err(LibraryError::LibraryDependencyError(format!(
+            "dependency cycle {} -> {}",
+            stack.join(" -> "),
+            name
+        )));
+    }
+
+    let library = &index.lib_map[name];
+    stack.push(name.to_owned());
+    for dependency in library.dependencies.iter() {
+        if !index.lib_map.contains_key(&dependency.name) {
+            return Err(LibraryError::LibraryDependencyError(format!(
+                "{} requires {}, which is not in the library index",
+                name, dependency.name
+            )));
+        }
+
+        if let Some(version) = &dependency.version {
+            let requirement = parse_requirement(name, version)?;
+            if let Some(installed) = installed_libraries.lib_map.get(&dependency.name) {
+                check_installed(name, installed, &requirement)?;
+            }
+            requirements
+                .entry(dependency.name.clone())
+                .or_default()
+                .push(requirement);
+        }
+        visit(
+            index,
+            installed_libraries,
+            &dependency.name,
+            stack,
+            order,
+            requirements,
+        )?;
+    }
+    stack.pop();
+    order.push(name.to_owned());
+
+    Ok(())
+}
+
+/// Parse a semver requirement given for a library, such as `^2.1`, `~1.4` or `>=1, <3`.
+pub fn parse_requirement(name: &str, requirement: &str) -> Result<VersionReq, LibraryError> {
+    VersionReq::parse(requirement).map_err(|e| {
+        LibraryError::LibraryDependencyError(format!(
+            "invalid version requirement {} for {}: {}",
+            requirement, name, e
+        ))
+    })
+}
+
+// check an installed dependency meets a requirement, libraries without a version pass with
+// a warning
+fn check_installed(
+    name: &str,
+    installed: &Library,
+    requirement: &VersionReq,
+) -> Result<(), LibraryError> {
+    match installed.version() {
+        Some(version) if requirement.matches(&version) => Ok(()),
+        Some(version) => Err(LibraryError::LibraryDependencyError(format!(
+            "{} requires {} {}, but version {} is installed",
+            name, installed.name, requirement, version
+        ))),
+        None => {
+            println!(
+                "{} has no version, not checking requirement {} of {}.",
+                installed.name, requirement, name
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Revision of the highest version of a library meeting all requirements, None without
+/// requirements.
+pub fn choose_version(
+    library: &Library,
+    requirements: &[VersionReq],
+    config: &Config,
+) -> Result<Option<String>, Box<dyn error::Error>> {
+    if requirements.is_empty() {
+        return Ok(None);
+    }
+
+    let versions = library.versions(config)?;
+    match pick_version(&versions, requirements) {
+        Some(revision) => Ok(Some(revision.clone())),
+        None => {
+            let requirements: Vec<String> = requirements.iter().map(|x| x.to_string()).collect();
+            let available: Vec<String> = versions.iter().map(|(x, _)| x.to_string()).collect();
+            Err(Box::new(LibraryError::LibraryDependencyError(format!(
+                "no version of {} meets {}, available: {}",
+                library.name,
+                requirements.join(", "),
+                if available.is_empty() {
+                    "none".to_owned()
+                } else {
+                    available.join(", ")
+                }
+            ))))
+        }
+    }
+}
+
+// revision of the first of versions, highest first, meeting every requirement
+fn pick_version<'a>(
+    versions: &'a [(Version, String)],
+    requirements: &[VersionReq],
+) -> Option<&'a String> {
+    versions
+        .iter()
+        .find(|(version, _)| requirements.iter().all(|x| x.matches(version)))
+        .map(|(_, revision)| revision)
+}
+
+/// Installed libraries depending on the library with the given name, sorted.
+pub fn dependents(installed_libraries: &Libraries, name: &str) -> Vec<String> {
+    let mut dependents: Vec<String> = installed_libraries
+        .lib_map
+        .values()
+        .filter(|library| library.dependencies.iter().any(|x| x.name == name))
+        .map(|library| library.name.clone())
+        .collect();
+    dependents.sort();
+
+    dependents
+}
+
+/// Uninstall libraries installed as dependencies that no installed library depends on anymore.
+pub fn autoremove(
+    config: Config,
+    global: bool,
+    dry_run: bool,
+) -> Result<(), Box<dyn error::Error>> {
+    let mut installed_libraries =
+        get_libraries(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))?;
+
+    // removing orphans can orphan their own dependencies
+    let mut removed = Vec::new();
+    loop {
+        let mut orphans: Vec<String> = installed_libraries
+            .lib_map
+            .values()
+            .filter(|library| library.dependency)
+            .filter(|library| dependents(&installed_libraries, &library.name).is_empty())
+            .map(|library| library.name.clone())
+            .collect();
+        if orphans.is_empty() {
+            break;
+        }
+        orphans.sort();
+
+        for name in orphans {
+            installed_libraries.lib_map.remove(&name);
+            removed.push(name);
+        }
+    }
+
+    if removed.is_empty() {
+        println!("No orphaned dependencies.");
+    }
+
+    // a dry run removes the orphans from an in-memory copy of installed.ron as it goes
+    let mut options = UninstallOptions::new();
+    options.dry_run(dry_run);
+    for name in removed.iter() {
+        println!("Removing {}...", name);
+        uninstall(config.clone(), global, name, &options)?;
+        options.removed(name);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pick_version;
+    use semver::{Version, VersionReq};
+
+    // tags of a library as Library::versions returns them, highest first
+    fn versions() -> Vec<(Version, String)> {
+        ["v3.0.0", "v2.2.0", "v2.1.5", "v2.1.0", "v1.9.9"]
+            .iter()
+            .map(|x| (Version::parse(&x[1..]).unwrap(), (*x).to_owned()))
+            .collect()
+    }
+
+    fn pick(requirements: &[&str]) -> Option<String> {
+        let requirements: Vec<VersionReq> = requirements
+            .iter()
+            .map(|x| VersionReq::parse(x).unwrap())
+            .collect();
+        pick_version(&versions(), &requirements).cloned()
+    }
+
+    #[test]
+    fn caret_picks_the_highest_compatible_version() {
+        assert_eq!(pick(&["^2.1"]), Some("v2.2.0".to_owned()));
+        assert_eq!(pick(&["^1"]), Some("v1.9.9".to_owned()));
+    }
+
+    #[test]
+    fn tilde_keeps_the_minor_version() {
+        assert_eq!(pick(&["~2.1"]), Some("v2.1.5".to_owned()));
+        assert_eq!(pick(&["~2.1.3"]), Some("v2.1.5".to_owned()));
+    }
+
+    #[test]
+    fn ranges_and_several_requirements_must_all_match() {
+        assert_eq!(pick(&[">=2.0, <2.2"]), Some("v2.1.5".to_owned()));
+        assert_eq!(pick(&["^2", "<2.1.5"]), Some("v2.1.0".to_owned()));
+        assert_eq!(pick(&["^2.1", "^3"]), None);
+        assert_eq!(pick(&[]), Some("v3.0.0".to_owned()));
+    }
+}
@@ -0,0 +1,438 @@
+use crate::cache;
+use crate::config::Config;
+use crate::git::{self, CloneOptions};
+use crate::impact::Contents;
+use crate::libraries::{get_libraries, Library, LibraryError, Source};
+use crate::sexpr::{self, Sexpr};
+use crate::staging::{staged_path, Staging};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error;
+use std::path::{Path, PathBuf};
+
+// pins or pads by number, each with a description and its definition, and fields by name
+#[derive(Default)]
+struct Parts {
+    pins: BTreeMap<String, Vec<(String, String)>>,
+    fields: BTreeMap<String, String>,
+}
+
+// atoms of a list after its head, e.g. 0 3.81 270 for (at 0 3.81 270)
+fn atoms(list: &Sexpr) -> Vec<&str> {
+    match list {
+        Sexpr::List(items) => items.iter().skip(1).filter_map(|x| x.atom()).collect(),
+        Sexpr::Atom(_) => Vec::new(),
+    }
+}
+
+// first child list of a list with the given head
+fn child<'a>(list: &'a Sexpr, key: &str) -> Option<&'a Sexpr> {
+    match list {
+        Sexpr::List(items) => items.iter().find(|x| x.head() == Some(key)),
+        Sexpr::Atom(_) => None,
+    }
+}
+
+impl Parts {
+    fn add_pin(&mut self, number: &str, description: String, definition: String) {
+        self.pins
+            .entry(number.to_owned())
+            .or_default()
+            .push((description, definition));
+    }
+
+    // pins and fields of a symbol, a DEF block of a .lib or a symbol of a .kicad_sym
+    fn of_symbol(definition: &str) -> Parts {
+        let mut parts = Parts::default();
+        if definition.starts_with("DEF ") {
+            for line in definition.lines() {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                match fields.first() {
+                    // X name number x y length orientation sizenum sizename unit convert type
+                    Some(&"X") if fields.len() > 11 => parts.add_pin(
+                        fields[2],
+                        format!(
+                            "{} ({}) at {} {}",
+                            fields[1], fields[11], fields[3], fields[4]
+                        ),
+                        line.to_owned(),
+                    ),
+                    // F0 "R" 0 50 50 H V C CNN, fields after F3 end with their name
+                    Some(field) if field.starts_with('F') && field[1..].parse::<u32>().is_ok() => {
+                        let quoted: Vec<&str> = line.split('"').skip(1).step_by(2).collect();
+                        let name = match &field[1..] {
+                            "0" => "Reference",
+                            "1" => "Value",
+                            "2" => "Footprint",
+                            "3" => "Datasheet",
+                            _ => quoted.get(1).copied().unwrap_or(field),
+                        };
+                        parts.fields.insert(
+                            name.to_owned(),
+                            quoted.first().copied().unwrap_or_default().to_owned(),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+            return parts;
+        }
+
+        for symbol in sexpr::parse(definition).unwrap_or_default().iter() {
+            for pin in symbol.find_all("pin") {
+                let number = child(pin, "number").and_then(|x| atoms(x).first().copied());
+                let name = child(pin, "name").and_then(|x| atoms(x).first().copied());
+                let at = child(pin, "at").map(atoms).unwrap_or_default();
+                let kind = atoms(pin).first().copied().unwrap_or_default();
+                parts.add_pin(
+                    number.unwrap_or_default(),
+                    format!(
+                        "{} ({}) at {}",
+                        name.unwrap_or_default(),
+                        kind,
+                        at.iter().take(2).copied().collect::<Vec<&str>>().join(" ")
+                    ),
+                    pin.to_string(),
+                );
+            }
+            parts.add_properties(symbol);
+        }
+        parts
+    }
+
+    // pads and fields of a .kicad_mod footprint
+    fn of_footprint(definition: &str) -> Parts {
+        let mut parts = Parts::default();
+        for footprint in sexpr::parse(definition).unwrap_or_default().iter() {
+            for pad in footprint.find_all("pad") {
+                let description = atoms(pad);
+                let at = child(pad, "at").map(atoms).unwrap_or_default();
+                let size = child(pad, "size").map(atoms).unwrap_or_default();
+                parts.add_pin(
+                    description.first().copied().unwrap_or_default(),
+                    format!(
+                        "{} at {} size {}",
+                        description
+                            .iter()
+                            .skip(1)
+                            .copied()
+                            .collect::<Vec<&str>>()
+                            .join(" "),
+                        at.iter().take(2).copied().collect::<Vec<&str>>().join(" "),
+                        size.join(" ")
+                    ),
+                    pad.to_string(),
+                );
+            }
+            parts.add_properties(footprint);
+
+            // footprints before KiCad 8 have texts instead of properties
+            for text in footprint.find_all("fp_text") {
+                if let [kind @ ("reference" | "value"), value, ..] = atoms(text)[..] {
+                    parts.fields.insert(kind.to_owned(), value.to_owned());
+                }
+            }
+            for key in ["descr", "tags", "attr"].iter() {
+                if let Some(list) = child(footprint, key) {
+                    parts
+                        .fields
+                        .insert((*key).to_owned(), atoms(list).join(" "));
+                }
+            }
+            let models: Vec<&str> = footprint
+                .find_all("model")
+                .into_iter()
+                .filter_map(|x| atoms(x).first().copied())
+                .collect();
+            if !models.is_empty() {
+                parts.fields.insert("model".to_owned(), models.join(", "));
+            }
+        }
+        parts
+    }
+
+    // (property "Value" "R" ...) children of a symbol or footprint, not of its units
+    fn add_properties(&mut self, list: &Sexpr) {
+        if let Sexpr::List(items) = list {
+            for property in items.iter().filter(|x| x.head() == Some("property")) {
+                if let [name, value, ..] = atoms(property)[..] {
+                    self.fields.insert(name.to_owned(), value.to_owned());
+                }
+            }
+        }
+    }
+
+    // changed pins or pads and fields from self to new
+    fn changes(&self, new: &Parts, kind: &str) -> Vec<String> {
+        let describe = |pins: &[(String, String)]| {
+            pins.iter()
+                .map(|(x, _)| x.clone())
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+
+        let mut changes = Vec::new();
+        let numbers: BTreeSet<&String> = self.pins.keys().chain(new.pins.keys()).collect();
+        for number in numbers {
+            match (self.pins.get(number), new.pins.get(number)) {
+                (None, Some(x)) => {
+                    changes.push(format!("added {} {}: {}", kind, number, describe(x)))
+                }
+                (Some(x), None) => {
+                    changes.push(format!("removed {} {}: {}", kind, number, describe(x)))
+                }
+                (Some(old), Some(x)) if old != x && describe(old) == describe(x) => {
+                    changes.push(format!("changed {} {}: {}", kind, number, describe(x)))
+                }
+                (Some(old), Some(x)) if old != x => changes.push(format!(
+                    "changed {} {}: {} -> {}",
+                    kind,
+                    number,
+                    describe(old),
+                    describe(x)
+                )),
+                _ => {}
+            }
+        }
+
+        let names: BTreeSet<&String> = self.fields.keys().chain(new.fields.keys()).collect();
+        for name in names {
+            match (self.fields.get(name), new.fields.get(name)) {
+                (None, Some(x)) => changes.push(format!("added field {}: \"{}\"", name, x)),
+                (Some(x), None) => changes.push(format!("removed field {}: \"{}\"", name, x)),
+                (Some(old), Some(x)) if old != x => {
+                    changes.push(format!("changed field {}: \"{}\" -> \"{}\"", name, old, x))
+                }
+                _ => {}
+            }
+        }
+
+        changes
+    }
+}
+
+// print the items added, removed and modified between two definitions maps, returns
+// whether anything changed
+fn print_changes(
+    title: &str,
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+    parts: fn(&str) -> Parts,
+    kind: &str,
+) -> bool {
+    let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    let mut lines = Vec::new();
+    for name in names {
+        match (old.get(name), new.get(name)) {
+            (None, Some(_)) => lines.push(format!("  + {}", name)),
+            (Some(_), None) => lines.push(format!("  - {}", name)),
+            (Some(old), Some(x)) if old != x => {
+                lines.push(format!("  ~ {}", name));
+                let changes = parts(old).changes(&parts(x), kind);
+                if changes.is_empty() {
+                    lines.push("      changed graphics or other attributes".to_owned());
+                }
+                for change in changes {
+                    lines.push(format!("      {}", change));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if lines.is_empty() {
+        return false;
+    }
+    println!("{}:", title);
+    for line in lines {
+        println!("{}", line);
+    }
+    true
+}
+
+// clone with the history of a library, its local repository or a clone in its installation
+// or the cache, cloned into the cache when there is none
+fn repository(config: &Config, library: &Library) -> Result<PathBuf, Box<dyn error::Error>> {
+    if let Some(path) = library.local_path() {
+        if path.join(".git").is_dir() {
+            return Ok(path);
+        }
+    }
+    if library.source != Source::Git || library.local_path().is_some() {
+        return Err(Box::new(LibraryError::LibraryNotGitError));
+    }
+
+    let url = config.rewrite_url(&library.url);
+    match cache::clone_path(&library.name) {
+        Some(path) if config.offline => Ok(path),
+        Some(path) => {
+            git::fetch_all(&path, &url)?;
+            Ok(path)
+        }
+        None if config.offline => Err(Box::new(LibraryError::LibraryNotCachedError)),
+        None => {
+            let path = cache::cached_path(&library.name);
+            git::clone(
+                &url,
+                path.to_string_lossy().into_owned(),
+                &CloneOptions::new(),
+            )?;
+            git::fetch_all(&path, &url)?;
+            Ok(path)
+        }
+    }
+}
+
+/// Print the symbols and footprints added, removed and modified between two revisions of a
+/// git library, with the pins, pads and fields that changed. from defaults to the installed
+/// commit, to to the revision the library index pins or the default branch.
+pub fn diff(
+    config: &Config,
+    name: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<(), Box<dyn error::Error>> {
+    let installed_libraries =
+        get_libraries(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))?;
+    let index = get_libraries(config.libraries.clone())?;
+
+    // an installation keeps its selection and nicknames, the index gets the configured ones
+    let library = match (
+        installed_libraries.lib_map.get(name),
+        index.lib_map.get(name),
+    ) {
+        (Some(installed), _) => installed.clone(),
+        (None, Some(library)) => {
+            let mut library = library.clone();
+            if let Some(prefix) = config.nickname_prefixes.get(name) {
+                library.nickname_prefix = Some(prefix.clone());
+            }
+            if let Some(selection) = config.selections.get(name) {
+                library.selection = selection.clone();
+            }
+            library
+        }
+        (None, None) => return Err(Box::new(LibraryError::LibraryNotFoundError)),
+    };
+    let repository = repository(config, &library)?;
+
+    let installed_commit = match installed_libraries.lib_map.get(name) {
+        Some(_) => git::head_commit(Path::new(&format!(
+            "{}/.kibrarian/extra/{}",
+            env!("HOME"),
+            name
+        ))),
+        None => None,
+    };
+    let from = match from.map(|x| x.to_owned()).or(installed_commit) {
+        Some(x) => x,
+        None => return Err(Box::new(LibraryError::LibraryNotInstalledError)),
+    };
+    let to = to
+        .map(|x| x.to_owned())
+        .or_else(|| index.lib_map.get(name).and_then(|x| x.revision.clone()))
+        .unwrap_or_else(|| "HEAD".to_owned());
+
+    let _staging = Staging::begin()?;
+    let mut contents = Vec::new();
+    for (revision, directory) in [(&from, "from"), (&to, "to")].iter() {
+        let destination = staged_path(&format!("{}@{}", name, directory));
+        let commit = git::checkout_to(&repository, revision, &destination)?;
+        if commit.starts_with(&revision[..]) {
+            println!("{} {}: {}", name, directory, commit);
+        } else {
+            println!("{} {}: {} ({})", name, directory, revision, commit);
+        }
+        contents.push(Contents::source(&library, &destination)?);
+    }
+
+    let symbols = print_changes(
+        "Symbols",
+        &contents[0].symbols,
+        &contents[1].symbols,
+        Parts::of_symbol,
+        "pin",
+    );
+    let footprints = print_changes(
+        "Footprints",
+        &contents[0].footprints,
+        &contents[1].footprints,
+        Parts::of_footprint,
+        "pad",
+    );
+    if !symbols && !footprints {
+        println!("No symbol or footprint changes.");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Parts;
+
+    const LEGACY: &str = "DEF R R 0 0 N Y 1 F N
+F0 \"R\" 80 0 50 V V C CNN
+F1 \"R\" 0 0 50 V V C CNN
+F2 \"\" -70 0 50 V I C CNN
+F4 \"1%\" 0 0 50 H I C CNN \"Tolerance\"
+DRAW
+X ~ 1 0 150 50 D 50 50 1 1 P
+X ~ 2 0 -150 50 U 50 50 1 1 P
+ENDDRAW
+ENDDEF";
+
+    #[test]
+    fn legacy_symbol_pins_and_fields() {
+        let parts = Parts::of_symbol(LEGACY);
+        assert_eq!(parts.pins.len(), 2);
+        assert_eq!(parts.pins["1"][0].0, "~ (P) at 0 150");
+        assert_eq!(parts.pins["2"][0].1, "X ~ 2 0 -150 50 U 50 50 1 1 P");
+        assert_eq!(parts.fields["Reference"], "R");
+        assert_eq!(parts.fields["Footprint"], "");
+        assert_eq!(parts.fields["Tolerance"], "1%");
+    }
+
+    #[test]
+    fn kicad_sym_pins_and_properties() {
+        let parts = Parts::of_symbol(
+            r#"(symbol "R" (property "Value" "R" (at 0 0 90))
+  (symbol "R_0_1" (pin passive line (at 0 3.81 270) (length 1.27) (name "~") (number "1")))
+  (symbol "R_1_1" (pin passive line (at 0 -3.81 90) (length 1.27) (name "~") (number "2"))))"#,
+        );
+        assert_eq!(parts.pins["1"][0].0, "~ (passive) at 0 3.81");
+        assert_eq!(parts.pins["2"][0].0, "~ (passive) at 0 -3.81");
+        assert_eq!(parts.fields["Value"], "R");
+    }
+
+    #[test]
+    fn kicad_mod_pads_and_fields() {
+        let parts = Parts::of_footprint(
+            r#"(footprint "R_0603" (layer "F.Cu") (descr "Resistor 0603") (tags "resistor")
+  (property "Reference" "REF**" (at 0 -1.43 0))
+  (fp_text value "R_0603" (at 0 1.43 0))
+  (pad "1" smd roundrect (at -0.825 0) (size 0.8 0.95) (layers "F.Cu"))
+  (pad "2" smd roundrect (at 0.825 0) (size 0.8 0.95) (layers "F.Cu"))
+  (model "${KICAD8_3DMODEL_DIR}/R_0603.wrl"))"#,
+        );
+        assert_eq!(parts.pins.len(), 2);
+        assert_eq!(
+            parts.pins["1"][0].0,
+            "smd roundrect at -0.825 0 size 0.8 0.95"
+        );
+        assert_eq!(parts.fields["Reference"], "REF**");
+        assert_eq!(parts.fields["value"], "R_0603");
+        assert_eq!(parts.fields["descr"], "Resistor 0603");
+        assert_eq!(parts.fields["model"], "${KICAD8_3DMODEL_DIR}/R_0603.wrl");
+    }
+
+    #[test]
+    fn changes_describe_moved_pins() {
+        let old = Parts::of_symbol(LEGACY);
+        let new = Parts::of_symbol(&LEGACY.replace("X ~ 2 0 -150", "X ~ 2 0 -200"));
+        assert_eq!(
+            old.changes(&new, "pin"),
+            vec!["changed pin 2: ~ (P) at 0 -150 -> ~ (P) at 0 -200"]
+        );
+        assert!(old.changes(&old, "pin").is_empty());
+    }
+}
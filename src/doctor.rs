@@ -0,0 +1,245 @@
+use crate::config::Config;
+use crate::lib_table::LibTable;
+use crate::libraries::{
+    get_libraries, installed_directories, remove_dir, save_libraries, Libraries, Library,
+};
+use std::path::Path;
+use std::{error, fs, io};
+
+/// Cross-check config.ron, installed.ron, the extra sources, the installed library
+/// directories and both lib tables, repairing what can be repaired when fix is set.
+///
+/// Returns false if problems were found and left unfixed.
+pub fn doctor(config: Config, fix: bool) -> Result<bool, Box<dyn error::Error>> {
+    let installed_path = format!("{}/.config/kibrarian/installed.ron", env!("HOME"));
+    let extra_path = format!("{}/.kibrarian/extra", env!("HOME"));
+    let libraries_path = format!("{}/.kibrarian/libraries", env!("HOME"));
+    let mut problems = 0;
+    let mut remaining = 0;
+    let mut fixable = 0;
+
+    // report a problem, and whether doctor --fix can repair it
+    let mut report = |problem: String, can_fix: bool| {
+        problems += 1;
+        if can_fix && fix {
+            println!("fixing: {}", problem);
+        } else {
+            remaining += 1;
+            if can_fix {
+                fixable += 1;
+            }
+            println!("{}", problem);
+        }
+    };
+
+    // files referenced by config.ron
+    let index = if Path::new(&config.libraries).is_file() {
+        Some(get_libraries(config.libraries.clone())?)
+    } else {
+        report(
+            format!(
+                "libraries.ron not found at {}, run 'kibrarian setup'.",
+                config.libraries
+            ),
+            false,
+        );
+        None
+    };
+
+    for table in [&config.sym_lib_table, &config.fp_lib_table].iter() {
+        if !Path::new(table).is_file() {
+            println!("lib table not found at {}, it will be created.", table);
+        }
+    }
+
+    let mut installed_libraries = if Path::new(&installed_path).is_file() {
+        get_libraries(installed_path.clone())?
+    } else {
+        report(
+            format!("installed.ron not found at {}.", installed_path),
+            true,
+        );
+        Libraries::new()
+    };
+
+    let mut sym_lib_table = LibTable::load(&config.sym_lib_table, "sym_lib_table")?;
+    let mut fp_lib_table = LibTable::load(&config.fp_lib_table, "fp_lib_table")?;
+
+    // installed libraries
+    let mut names: Vec<String> = installed_libraries.lib_map.keys().cloned().collect();
+    names.sort();
+
+    for name in names.iter() {
+        let library = installed_libraries.lib_map[name].clone();
+
+        if let Some(index) = &index {
+            if !index.lib_map.contains_key(name) {
+                report(format!("[{}]: not found in libraries.ron.", name), false);
+            }
+        }
+
+        // adopted libraries live outside of the kibrarian directories
+        if library.adopted {
+            check_rows(
+                &library,
+                &mut sym_lib_table,
+                &mut fp_lib_table,
+                fix,
+                &mut report,
+            );
+            continue;
+        }
+
+        if !Path::new(&format!("{}/{}", extra_path, name)).exists() {
+            report(
+                format!(
+                    "[{}]: source missing from {}/{}, reinstall to restore it.",
+                    name, extra_path, name
+                ),
+                false,
+            );
+        }
+
+        let directories = installed_directories(name);
+        if directories
+            .iter()
+            .all(|x| !Path::new(&format!("{}/{}", libraries_path, x)).exists())
+        {
+            report(
+                format!("[{}]: installed files missing, forgetting library.", name),
+                true,
+            );
+            if fix {
+                for row in library.sym_lib_rows.iter() {
+                    sym_lib_table.remove(&row.name);
+                }
+                for row in library.fp_lib_rows.iter() {
+                    fp_lib_table.remove(&row.name);
+                }
+                installed_libraries.lib_map.remove(name);
+            }
+            continue;
+        }
+
+        check_rows(
+            &library,
+            &mut sym_lib_table,
+            &mut fp_lib_table,
+            fix,
+            &mut report,
+        );
+    }
+
+    // orphaned sources and installed library directories
+    for directory in [
+        extra_path.clone(),
+        format!("{}/symbols", libraries_path),
+        format!("{}/footprints", libraries_path),
+    ]
+    .iter()
+    {
+        for orphan in orphans(directory, &installed_libraries)? {
+            report(format!("orphaned directory {}.", orphan), true);
+            if fix {
+                remove_dir(&orphan)?;
+            }
+        }
+    }
+
+    // orphaned lib-table rows pointing into the kibrarian libraries directory
+    for (table, table_name) in [
+        (&mut sym_lib_table, "sym-lib-table"),
+        (&mut fp_lib_table, "fp-lib-table"),
+    ]
+    .iter_mut()
+    {
+        let orphaned: Vec<String> = table
+            .rows
+            .iter()
+            .filter(|row| row.uri.starts_with(&libraries_path[..]))
+            .filter(|row| {
+                !installed_libraries.lib_map.values().any(|library| {
+                    library.sym_lib_rows.contains(row) || library.fp_lib_rows.contains(row)
+                })
+            })
+            .map(|row| row.name.clone())
+            .collect();
+
+        for name in orphaned {
+            report(format!("orphaned {} row {}.", table_name, name), true);
+            if fix {
+                table.remove(&name);
+            }
+        }
+    }
+
+    if fix {
+        sym_lib_table.save(&config.sym_lib_table)?;
+        fp_lib_table.save(&config.fp_lib_table)?;
+        save_libraries(&installed_libraries, installed_path)?;
+    }
+
+    if problems == 0 {
+        println!("No problems found.");
+    } else if fixable > 0 {
+        println!(
+            "Run 'kibrarian doctor --fix' to repair {} problem(s).",
+            fixable
+        );
+    }
+
+    Ok(remaining == 0)
+}
+
+// restore the recorded lib-table rows of a library that are missing or changed
+fn check_rows<F: FnMut(String, bool)>(
+    library: &Library,
+    sym_lib_table: &mut LibTable,
+    fp_lib_table: &mut LibTable,
+    fix: bool,
+    report: &mut F,
+) {
+    for (table, rows, table_name) in [
+        (sym_lib_table, &library.sym_lib_rows, "sym-lib-table"),
+        (fp_lib_table, &library.fp_lib_rows, "fp-lib-table"),
+    ]
+    .iter_mut()
+    {
+        for row in rows.iter() {
+            if table.find(&row.name) != Some(row) {
+                report(
+                    format!(
+                        "[{}]: {} row {} missing or changed.",
+                        library.name, table_name, row.name
+                    ),
+                    true,
+                );
+                if fix {
+                    table.remove(&row.name);
+                    table.add(row.clone());
+                }
+            }
+        }
+    }
+}
+
+// entries of directory that do not belong to an installed library
+fn orphans(directory: &str, installed: &Libraries) -> io::Result<Vec<String>> {
+    let mut orphans = Vec::new();
+    let entries = match fs::read_dir(directory) {
+        Ok(x) => x,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(orphans),
+        Err(e) => return Err(e),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !installed.lib_map.contains_key(&name) {
+            orphans.push(entry.path().to_string_lossy().into_owned());
+        }
+    }
+    orphans.sort();
+
+    Ok(orphans)
+}
@@ -0,0 +1,219 @@
+use crate::adopt::normalize_url;
+use crate::batch::Summary;
+use crate::config::Config;
+use crate::git;
+use crate::history::write_atomic;
+use crate::libraries::{get_libraries, install, InstallMode, InstallOptions, Library, Selection};
+use ron::de::from_reader;
+use ron::ser::{self, PrettyConfig};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::{error, fs::File};
+
+/// Installed libraries of a machine, written by export and installed again by import.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Export {
+    pub libraries: Vec<ExportedLibrary>,
+}
+
+/// An installed library with the commit it was installed at and the nicknames it got.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportedLibrary {
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub revision: Option<String>,
+    #[serde(default)]
+    pub commit: Option<String>,
+    #[serde(default = "default_global")]
+    pub global: bool,
+    #[serde(default)]
+    pub mode: InstallMode,
+    #[serde(default)]
+    pub selection: Selection,
+    #[serde(default)]
+    pub dependency: bool,
+    #[serde(default)]
+    pub nickname_prefix: Option<String>,
+    #[serde(default)]
+    pub sym_nicknames: Vec<String>,
+    #[serde(default)]
+    pub fp_nicknames: Vec<String>,
+}
+
+fn default_global() -> bool {
+    true
+}
+
+fn installation_path(name: &str) -> String {
+    format!("{}/.kibrarian/extra/{}", env!("HOME"), name)
+}
+
+impl ExportedLibrary {
+    fn new(library: &Library) -> ExportedLibrary {
+        ExportedLibrary {
+            name: library.name.clone(),
+            url: library.url.clone(),
+            revision: library.revision.clone(),
+            commit: git::head_commit(Path::new(&installation_path(&library.name))),
+            // only global installations are implemented
+            global: true,
+            mode: library.mode,
+            selection: library.selection.clone(),
+            dependency: library.dependency,
+            nickname_prefix: library.nickname_prefix.clone(),
+            sym_nicknames: library
+                .sym_lib_rows
+                .iter()
+                .map(|x| x.name.clone())
+                .collect(),
+            fp_nicknames: library.fp_lib_rows.iter().map(|x| x.name.clone()).collect(),
+        }
+    }
+
+    // differences between this library and the same library installed on this machine
+    fn differences(&self, installed: &Library) -> Vec<String> {
+        let mut differences = Vec::new();
+        let commit = git::head_commit(Path::new(&installation_path(&self.name)));
+        if let (Some(exported), Some(commit)) = (&self.commit, &commit) {
+            if exported != commit {
+                differences.push(format!("commit {} instead of {}", commit, exported));
+            }
+        }
+
+        let sym_nicknames: Vec<String> = installed
+            .sym_lib_rows
+            .iter()
+            .map(|x| x.name.clone())
+            .collect();
+        let fp_nicknames: Vec<String> = installed
+            .fp_lib_rows
+            .iter()
+            .map(|x| x.name.clone())
+            .collect();
+        if sym_nicknames != self.sym_nicknames || fp_nicknames != self.fp_nicknames {
+            differences.push(format!(
+                "nicknames {} instead of {}",
+                [sym_nicknames, fp_nicknames].concat().join(", "),
+                [self.sym_nicknames.clone(), self.fp_nicknames.clone()]
+                    .concat()
+                    .join(", ")
+            ));
+        }
+
+        differences
+    }
+}
+
+/// Write the installed libraries with their commits, nicknames and scope to path.
+pub fn export(path: &str) -> Result<(), Box<dyn error::Error>> {
+    let installed_libraries =
+        get_libraries(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))?;
+
+    let mut libraries: Vec<&Library> = installed_libraries.lib_map.values().collect();
+    libraries.sort_by(|a, b| a.name.cmp(&b.name));
+    let export = Export {
+        libraries: libraries.into_iter().map(ExportedLibrary::new).collect(),
+    };
+
+    let serialized = ser::to_string_pretty(&export, PrettyConfig::default())?;
+    write_atomic(path, serialized.as_bytes())?;
+    println!("Exported {} libraries to {}", export.libraries.len(), path);
+
+    Ok(())
+}
+
+/// Install the libraries of an export at their exported revisions, dependencies first,
+/// reporting libraries missing from the local index and libraries that don't match the
+/// export after installation. Returns whether the whole set was installed as exported.
+pub fn import(config: Config, path: &str, dry_run: bool) -> Result<bool, Box<dyn error::Error>> {
+    let export: Export = from_reader(File::open(path)?)?;
+    let index = get_libraries(config.libraries.clone())?;
+    let installed_libraries =
+        get_libraries(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))?;
+    let mut summary = Summary::new("Imported");
+
+    let mut libraries: Vec<&ExportedLibrary> = export.libraries.iter().collect();
+    libraries.sort_by_key(|x| !x.dependency);
+
+    println!("Plan:");
+    let mut planned = Vec::new();
+    for exported in libraries {
+        let problem = match index.lib_map.get(&exported.name) {
+            None => Some("not in the library index".to_owned()),
+            Some(library) if normalize_url(&library.url) != normalize_url(&exported.url) => {
+                Some(format!("library index has url {}", library.url))
+            }
+            Some(_) if !exported.global => Some("project installations are unsupported".to_owned()),
+            Some(_) => None,
+        };
+        if let Some(problem) = problem {
+            println!("  skip {}: {}", exported.name, problem);
+            summary.failed.push((exported.name.clone(), problem));
+            continue;
+        }
+
+        if let Some(installed) = installed_libraries.lib_map.get(&exported.name) {
+            let differences = exported.differences(installed);
+            if differences.is_empty() {
+                println!("  keep {}: already installed", exported.name);
+                summary.succeeded.push(exported.name.clone());
+            } else {
+                let problem = format!("installed with {}", differences.join(", "));
+                println!("  skip {}: {}", exported.name, problem);
+                summary.failed.push((exported.name.clone(), problem));
+            }
+            continue;
+        }
+
+        match exported.commit.as_ref().or(exported.revision.as_ref()) {
+            Some(revision) => println!("  install {} at {}", exported.name, revision),
+            None => println!("  install {}", exported.name),
+        }
+        planned.push(exported);
+    }
+
+    for exported in planned {
+        println!("Installing {}...", exported.name);
+
+        // the exported commit reproduces the installation even if its revision is a branch
+        // or a moved tag, the revision only serves exports without a commit
+        let mut options = InstallOptions::new();
+        options.mode(exported.mode);
+        options.selection(exported.selection.clone());
+        options.dependency(exported.dependency);
+        options.dry_run(dry_run);
+        if let Some(revision) = exported.commit.as_ref().or(exported.revision.as_ref()) {
+            options.revision(revision.clone());
+        }
+
+        let mut config = config.clone();
+        if let Some(prefix) = &exported.nickname_prefix {
+            config
+                .nickname_prefixes
+                .insert(exported.name.clone(), prefix.clone());
+        }
+
+        let result = install(config, exported.global, &exported.name, &options);
+        if result.is_err() || dry_run {
+            summary.record(&exported.name, result);
+            continue;
+        }
+
+        let installed_libraries =
+            get_libraries(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))?;
+        let differences = match installed_libraries.lib_map.get(&exported.name) {
+            Some(installed) => exported.differences(installed),
+            None => vec!["not recorded in installed.ron".to_owned()],
+        };
+        if differences.is_empty() {
+            summary.succeeded.push(exported.name.clone());
+        } else {
+            let problem = format!("installed with {}", differences.join(", "));
+            println!("{}", problem);
+            summary.failed.push((exported.name.clone(), problem));
+        }
+    }
+
+    Ok(summary.print())
+}
@@ -1,8 +1,14 @@
+use crate::progress::ProgressBoard;
 use git2::build::{CheckoutBuilder, RepoBuilder};
-use git2::{FetchOptions, Progress, RemoteCallbacks};
+use git2::{
+    Commit, Direction, FetchOptions, FetchPrune, Progress, Remote, RemoteCallbacks, Repository,
+    TreeWalkMode, TreeWalkResult,
+};
 use std::cell::RefCell;
+use std::fs;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 struct State {
     progress: Option<Progress<'static>>,
@@ -10,32 +16,33 @@ struct State {
     current: usize,
     path: Option<PathBuf>,
     newline: bool,
+    board: Option<(Arc<ProgressBoard>, usize)>,
 }
 
 fn print(state: &mut State) {
-    let stats = state.progress.as_ref().unwrap();
-    let network_pct = (100 * stats.received_objects()) / stats.total_objects();
-    let index_pct = (100 * stats.indexed_objects()) / stats.total_objects();
-    let co_pct = if state.total > 0 {
-        (100 * state.current) / state.total
-    } else {
-        0
+    // checking out a cached clone offline transfers nothing
+    let stats = match state.progress.as_ref() {
+        Some(x) => x,
+        None => return,
     };
+    let network_pct = (100 * stats.received_objects())
+        .checked_div(stats.total_objects())
+        .unwrap_or(0);
+    let index_pct = (100 * stats.indexed_objects())
+        .checked_div(stats.total_objects())
+        .unwrap_or(0);
+    let co_pct = (100 * state.current).checked_div(state.total).unwrap_or(0);
     let kbytes = stats.received_bytes() / 1024;
-    if stats.received_objects() == stats.total_objects() {
-        if !state.newline {
-            println!();
-            state.newline = true;
-        }
-        print!(
-            "Resolving deltas {}/{}\r",
+    let text = if stats.received_objects() == stats.total_objects() {
+        format!(
+            "Resolving deltas {}/{}",
             stats.indexed_deltas(),
             stats.total_deltas()
-        );
+        )
     } else {
-        print!(
+        format!(
             "net {:3}% ({:4} kb, {:5}/{:5})  /  idx {:3}% ({:5}/{:5})  \
-             /  chk {:3}% ({:4}/{:4}) {}\r",
+             /  chk {:3}% ({:4}/{:4}) {}",
             network_pct,
             kbytes,
             stats.received_objects(),
@@ -52,12 +59,82 @@ fn print(state: &mut State) {
                 .map(|s| s.to_string_lossy().into_owned())
                 .unwrap_or_default()
         )
+    };
+
+    // concurrent clones each update their own line
+    if let Some((board, line)) = &state.board {
+        board.set(*line, &text);
+        return;
     }
+
+    if stats.received_objects() == stats.total_objects() && !state.newline {
+        println!();
+        state.newline = true;
+    }
+    print!("{}\r", text);
     io::stdout().flush().unwrap();
 }
 
-pub fn clone(url: &str, destination: String) -> Result<(), git2::Error> {
-    println!("cloning from: {}", url);
+/// Options controlling how a repository is cloned.
+pub struct CloneOptions {
+    revision: Option<String>,
+    paths: Vec<String>,
+    // a declared path is the repository root, nothing is excluded from the checkout
+    whole_tree: bool,
+    full_fallback: bool,
+    progress: Option<(Arc<ProgressBoard>, usize)>,
+}
+
+impl CloneOptions {
+    pub fn new() -> CloneOptions {
+        CloneOptions {
+            revision: None,
+            paths: Vec::new(),
+            whole_tree: false,
+            full_fallback: true,
+            progress: None,
+        }
+    }
+
+    /// Pin the clone to a tag, branch or commit, fetched with depth 1.
+    pub fn revision(&mut self, revision: &str) {
+        self.revision = Some(revision.to_owned());
+    }
+
+    /// Restrict the checkout to the given path, may be called multiple times. The repository
+    /// root lifts the restriction.
+    pub fn path(&mut self, path: &str) {
+        match path.trim_start_matches("./").trim_matches('/') {
+            "" | "." => self.whole_tree = true,
+            _ => self.paths.push(path.to_owned()),
+        }
+    }
+
+    // paths the checkout is restricted to, none for the whole tree
+    fn checkout_paths(&self) -> &[String] {
+        if self.whole_tree {
+            &[]
+        } else {
+            &self.paths
+        }
+    }
+
+    /// Fetch the full history if the server refuses a shallow fetch.
+    pub fn full_fallback(&mut self, full_fallback: bool) {
+        self.full_fallback = full_fallback;
+    }
+
+    /// Report progress on a line of a board shared with other clones instead of stdout.
+    pub fn progress(&mut self, board: Arc<ProgressBoard>, line: usize) {
+        self.progress = Some((board, line));
+    }
+}
+
+pub fn clone(url: &str, destination: String, options: &CloneOptions) -> Result<(), git2::Error> {
+    match &options.progress {
+        Some((board, line)) => board.set(*line, "connecting"),
+        None => println!("cloning from: {}", url),
+    }
 
     let state = RefCell::new(State {
         progress: None,
@@ -65,32 +142,457 @@ pub fn clone(url: &str, destination: String) -> Result<(), git2::Error> {
         current: 0,
         path: None,
         newline: false,
+        board: options.progress.clone(),
+    });
+
+    let mut co = CheckoutBuilder::new();
+    for path in options.checkout_paths() {
+        co.path(&path[..]);
+    }
+    co.progress(|path, cur, total| {
+        let mut state = state.borrow_mut();
+        state.path = path.map(|p| p.to_path_buf());
+        state.current = cur;
+        state.total = total;
+        print(&mut state);
     });
 
+    if let Some(revision) = &options.revision {
+        let repo = Repository::init(Path::new(&destination[..]))?;
+
+        match fetch(&repo, url, revision, shallow_depth(revision), &state) {
+            Ok(()) => {}
+            Err(e) if options.full_fallback => {
+                match &options.progress {
+                    Some((board, line)) => board.set(*line, "shallow fetch failed, fetching all"),
+                    None => println!(
+                        "\nshallow fetch failed ({}), falling back to full clone",
+                        e.message()
+                    ),
+                }
+                fetch(&repo, url, revision, 0, &state)?;
+            }
+            Err(e) => return Err(e),
+        }
+
+        let commit = resolve(&repo, revision)?;
+        co.force();
+        repo.checkout_tree(commit.as_object(), Some(&mut co))?;
+        repo.set_head_detached(commit.id())?;
+    } else {
+        let mut cb = RemoteCallbacks::new();
+        cb.transfer_progress(|stats| {
+            let mut state = state.borrow_mut();
+            state.progress = Some(stats.to_owned());
+            print(&mut state);
+            true
+        });
+
+        let mut fo = FetchOptions::new();
+        fo.remote_callbacks(cb);
+        RepoBuilder::new()
+            .fetch_options(fo)
+            .with_checkout(co)
+            .clone(url, Path::new(&destination[..]))?;
+    }
+    if options.progress.is_none() {
+        println!();
+    }
+
+    Ok(())
+}
+
+// fetch a single revision from url into the origin remote, or every branch and tag for *,
+// depth 0 fetches the full history
+fn fetch(
+    repo: &Repository,
+    url: &str,
+    revision: &str,
+    depth: i32,
+    state: &RefCell<State>,
+) -> Result<(), git2::Error> {
+    let mut remote = match repo.find_remote("origin") {
+        Ok(x) => x,
+        Err(_) => repo.remote("origin", url)?,
+    };
+
     let mut cb = RemoteCallbacks::new();
     cb.transfer_progress(|stats| {
         let mut state = state.borrow_mut();
         state.progress = Some(stats.to_owned());
-        print(&mut *state);
+        print(&mut state);
         true
     });
 
+    let mut fo = FetchOptions::new();
+    fo.remote_callbacks(cb);
+    fo.depth(depth);
+
+    let tag = format!("+refs/tags/{0}:refs/tags/{0}", revision);
+    let branch = format!("+refs/heads/{0}:refs/remotes/origin/{0}", revision);
+    let refspecs = if revision == "HEAD" {
+        vec!["+HEAD:refs/remotes/origin/HEAD"]
+    } else if revision == "*" {
+        vec![
+            "+HEAD:refs/remotes/origin/HEAD",
+            "+refs/heads/*:refs/remotes/origin/*",
+            "+refs/tags/*:refs/tags/*",
+        ]
+    } else if is_commit_id(revision) && depth == 0 {
+        // many servers only serve advertised objects, fetch everything to find the commit
+        vec![
+            "+refs/heads/*:refs/remotes/origin/*",
+            "+refs/tags/*:refs/tags/*",
+        ]
+    } else if is_commit_id(revision) {
+        vec![revision]
+    } else {
+        vec![&tag[..], &branch[..]]
+    };
+
+    remote.fetch(&refspecs, Some(&mut fo), None)
+}
+
+/// Fetch the revision in options, or the default branch of url without one, into the clone
+/// at path without changing its checkout, unless the clone already has the pinned commit.
+pub fn prefetch(path: &Path, url: &str, options: &CloneOptions) -> Result<(), git2::Error> {
+    let repo = Repository::open(path)?;
+    let revision = options.revision.as_deref().unwrap_or("HEAD");
+
+    // a pinned commit may already be in the clone
+    let present = is_commit_id(revision)
+        && repo
+            .revparse_single(revision)
+            .and_then(|x| x.peel_to_commit())
+            .is_ok();
+    if present {
+        return Ok(());
+    }
+
+    match &options.progress {
+        Some((board, line)) => board.set(*line, "fetching"),
+        None => println!("fetching from: {}", url),
+    }
+
+    // the url in the library index may have changed since the clone was made
+    repo.remote_set_url("origin", url)?;
+
+    let state = RefCell::new(State {
+        progress: None,
+        total: 0,
+        current: 0,
+        path: None,
+        newline: false,
+        board: options.progress.clone(),
+    });
+
+    // pinned clones are shallow, unpinned clones have the full history
+    let depth = options.revision.as_deref().map_or(0, shallow_depth);
+    match fetch(&repo, url, revision, depth, &state) {
+        Err(_) if depth == 1 && options.full_fallback => fetch(&repo, url, revision, 0, &state),
+        result => result,
+    }
+}
+
+/// Bring an existing clone to the revision in options, or the default branch of url without
+/// one, fetching it from url first unless offline.
+pub fn refresh(
+    path: &Path,
+    url: &str,
+    options: &CloneOptions,
+    offline: bool,
+) -> Result<(), git2::Error> {
+    if !offline {
+        prefetch(path, url, options)?;
+    }
+
+    let repo = Repository::open(path)?;
+    let revision = options.revision.as_deref().unwrap_or("HEAD");
+    let state = RefCell::new(State {
+        progress: None,
+        total: 0,
+        current: 0,
+        path: None,
+        newline: false,
+        board: options.progress.clone(),
+    });
+
+    let commit = match resolve(&repo, revision) {
+        Ok(x) => x,
+        Err(_) if offline => {
+            return Err(git2::Error::from_str(&format!(
+                "{} is not in the cached clone, it can't be fetched offline",
+                revision
+            )))
+        }
+        Err(e) => return Err(e),
+    };
+
     let mut co = CheckoutBuilder::new();
+    for path in options.checkout_paths() {
+        co.path(&path[..]);
+    }
+    co.force();
     co.progress(|path, cur, total| {
         let mut state = state.borrow_mut();
         state.path = path.map(|p| p.to_path_buf());
         state.current = cur;
         state.total = total;
-        print(&mut *state);
+        print(&mut state);
+    });
+    repo.checkout_tree(commit.as_object(), Some(&mut co))?;
+    repo.set_head_detached(commit.id())?;
+
+    if options.progress.is_none() && !offline {
+        println!();
+    }
+
+    Ok(())
+}
+
+/// Fetch every branch and tag of url into the clone at path without changing its checkout.
+pub fn fetch_all(path: &Path, url: &str) -> Result<(), git2::Error> {
+    println!("fetching from: {}", url);
+
+    let repo = Repository::open(path)?;
+    repo.remote_set_url("origin", url)?;
+    let state = RefCell::new(State {
+        progress: None,
+        total: 0,
+        current: 0,
+        path: None,
+        newline: false,
+        board: None,
+    });
+    fetch(&repo, url, "*", 0, &state)?;
+    println!();
+
+    Ok(())
+}
+
+/// Commit a revision of the clone at path points to.
+pub fn commit_id(path: &Path, revision: &str) -> Result<String, git2::Error> {
+    let repo = Repository::open(path)?;
+    let commit = resolve(&repo, revision)?;
+    Ok(commit.id().to_string())
+}
+
+/// Write the files of a revision of the clone at path to destination, leaving the checkout of
+/// the clone alone. Returns the commit the revision points to.
+pub fn checkout_to(path: &Path, revision: &str, destination: &Path) -> Result<String, git2::Error> {
+    let repo = Repository::open(path)?;
+    let commit = resolve(&repo, revision)?;
+
+    let mut result = Ok(());
+    commit
+        .tree()?
+        .walk(TreeWalkMode::PreOrder, |directory, entry| {
+            let blob = match entry.to_object(&repo).map(|x| x.into_blob()) {
+                Ok(Ok(x)) => x,
+                _ => return TreeWalkResult::Ok,
+            };
+            let file = destination
+                .join(directory)
+                .join(entry.name().unwrap_or_default());
+            result = file
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| fs::write(&file, blob.content()));
+            match result {
+                Ok(()) => TreeWalkResult::Ok,
+                Err(_) => TreeWalkResult::Abort,
+            }
+        })?;
+    result.map_err(|e| git2::Error::from_str(&e.to_string()))?;
+
+    Ok(commit.id().to_string())
+}
+
+// find the commit a fetched revision points to
+fn resolve<'a>(repo: &'a Repository, revision: &str) -> Result<Commit<'a>, git2::Error> {
+    let candidates = [
+        format!("refs/tags/{}", revision),
+        format!("refs/remotes/origin/{}", revision),
+        revision.to_owned(),
+    ];
+
+    let mut last_error = None;
+    for candidate in candidates.iter() {
+        match repo.revparse_single(&candidate[..]) {
+            Ok(object) => return object.peel_to_commit(),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap())
+}
+
+// full or abbreviated commit id, as git abbreviates them to at least 7 digits
+fn is_commit_id(revision: &str) -> bool {
+    (7..=40).contains(&revision.len()) && revision.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// depth a revision is fetched with, abbreviated commits can only be found in the full history
+fn shallow_depth(revision: &str) -> i32 {
+    if is_commit_id(revision) && revision.len() < 40 {
+        0
+    } else {
+        1
+    }
+}
+
+/// Fetch origin, its url passed through rewrite_url, and fast-forward the checked out branch
+/// of the repository at path.
+pub fn pull<F: Fn(&str) -> String>(path: &str, rewrite_url: F) -> Result<(), git2::Error> {
+    println!("pulling: {}", path);
+
+    let repo = Repository::open(path)?;
+    let head = repo.head()?;
+    let branch = match head.shorthand() {
+        Some(x) if head.is_branch() => x.to_owned(),
+        _ => return Err(git2::Error::from_str("HEAD is not on a branch")),
+    };
+
+    // fetch the origin url through the configured url rewrites
+    let url = rewrite_url(repo.find_remote("origin")?.url().unwrap_or_default());
+    let mut remote = repo.remote_anonymous(&url)?;
+    remote.fetch(&["+refs/heads/*:refs/remotes/origin/*"], None, None)?;
+
+    let upstream = repo
+        .find_reference(&format!("refs/remotes/origin/{}", branch)[..])?
+        .peel_to_commit()?;
+    let annotated = repo.find_annotated_commit(upstream.id())?;
+    let (analysis, _) = repo.merge_analysis(&[&annotated])?;
+
+    if analysis.is_up_to_date() {
+        println!("already up to date");
+    } else if analysis.is_fast_forward() {
+        let mut reference = repo.find_reference(head.name().unwrap_or_default())?;
+        reference.set_target(upstream.id(), "kibrarian: fast-forward")?;
+        repo.checkout_head(Some(CheckoutBuilder::new().force()))?;
+    } else {
+        return Err(git2::Error::from_str(
+            "local branch has diverged from origin",
+        ));
+    }
+
+    Ok(())
+}
+
+/// The origin url of the repository containing path, if there is one.
+pub fn remote_url(path: &Path) -> Option<String> {
+    let repo = Repository::discover(path).ok()?;
+    let remote = repo.find_remote("origin").ok()?;
+    remote.url().map(|x| x.to_owned())
+}
+
+/// Tag names of a remote repository, listed without cloning it.
+pub fn remote_tags(url: &str) -> Result<Vec<String>, git2::Error> {
+    let mut remote = Remote::create_detached(url)?;
+    remote.connect(Direction::Fetch)?;
+
+    let tags = remote
+        .list()?
+        .iter()
+        .filter_map(|head| head.name().strip_prefix("refs/tags/"))
+        .filter(|name| !name.ends_with("^{}"))
+        .map(|name| name.to_owned())
+        .collect();
+
+    Ok(tags)
+}
+
+/// Create or update a bare mirror of url at destination, with every branch and tag.
+pub fn mirror(url: &str, destination: &Path) -> Result<(), git2::Error> {
+    println!("mirroring: {}", url);
+
+    let repo = if destination.exists() {
+        Repository::open_bare(destination)?
+    } else {
+        Repository::init_bare(destination)?
+    };
+    let mut remote = match repo.find_remote("origin") {
+        Ok(x) => x,
+        Err(_) => repo.remote_with_fetch("origin", url, "+refs/*:refs/*")?,
+    };
+
+    let state = RefCell::new(State {
+        progress: None,
+        total: 0,
+        current: 0,
+        path: None,
+        newline: false,
+        board: None,
+    });
+    let mut cb = RemoteCallbacks::new();
+    cb.transfer_progress(|stats| {
+        let mut state = state.borrow_mut();
+        state.progress = Some(stats.to_owned());
+        print(&mut state);
+        true
     });
 
     let mut fo = FetchOptions::new();
     fo.remote_callbacks(cb);
-    RepoBuilder::new()
-        .fetch_options(fo)
-        .with_checkout(co)
-        .clone(url, Path::new(&destination[..]))?;
+    fo.prune(FetchPrune::On);
+    remote.fetch::<&str>(&[], Some(&mut fo), None)?;
     println!();
 
     Ok(())
 }
+
+/// Commit checked out in the repository at path.
+pub fn head_commit(path: &Path) -> Option<String> {
+    let repo = Repository::open(path).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+    Some(commit.id().to_string())
+}
+
+/// Tag names of a local repository.
+pub fn local_tags(path: &Path) -> Result<Vec<String>, git2::Error> {
+    let repo = Repository::open(path)?;
+    let tags = repo
+        .tag_names(None)?
+        .iter()
+        .flatten()
+        .map(|name| name.to_owned())
+        .collect();
+
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_commit_id, shallow_depth, CloneOptions};
+
+    #[test]
+    fn commit_ids_may_be_abbreviated() {
+        assert!(is_commit_id("b9b417ef9b59f248e47beededee34bcc09b39c41"));
+        assert!(is_commit_id("b9b417e"));
+        assert!(is_commit_id("B9B417EF9B"));
+        assert!(!is_commit_id("b9b417"));
+        assert!(!is_commit_id("b9b417ef9b59f248e47beededee34bcc09b39c41a"));
+        assert!(!is_commit_id("v2.1.0"));
+        assert!(!is_commit_id("main"));
+    }
+
+    #[test]
+    fn abbreviated_commits_fetch_the_full_history() {
+        assert_eq!(shallow_depth("b9b417ef9b59f248e47beededee34bcc09b39c41"), 1);
+        assert_eq!(shallow_depth("b9b417e"), 0);
+        assert_eq!(shallow_depth("v2.1.0"), 1);
+    }
+
+    #[test]
+    fn the_repository_root_checks_out_the_whole_tree() {
+        let mut options = CloneOptions::new();
+        options.path("symbols");
+        assert_eq!(options.checkout_paths(), ["symbols"]);
+        for root in ["", ".", "./", "/"].iter() {
+            let mut options = CloneOptions::new();
+            options.path("symbols");
+            options.path(root);
+            assert!(options.checkout_paths().is_empty(), "{:?}", root);
+        }
+    }
+}
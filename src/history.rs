@@ -0,0 +1,210 @@
+use crate::config::Config;
+use chrono::{Local, TimeZone};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{error, fs, io, process};
+
+// number of snapshots kept in the history directory
+const HISTORY_LIMIT: usize = 50;
+
+/// Write a file by writing a temporary file next to it and renaming it into place,
+/// so a crash never leaves a half written file behind.
+pub fn write_atomic<P: AsRef<Path>>(path: P, contents: &[u8]) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(format!(".tmp-{}", process::id()));
+    let temp_path = PathBuf::from(temp_path);
+
+    let mut temp_file = fs::File::create(&temp_path)?;
+    temp_file.write_all(contents)?;
+    temp_file.sync_all()?;
+    drop(temp_file);
+
+    fs::rename(&temp_path, path)
+}
+
+fn history_path() -> PathBuf {
+    PathBuf::from(format!("{}/.kibrarian/history", env!("HOME")))
+}
+
+// files backed up in each snapshot, with the name they are stored under
+fn tracked_files(config: &Config) -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "installed.ron",
+            format!("{}/.config/kibrarian/installed.ron", env!("HOME")),
+        ),
+        ("sym-lib-table", config.sym_lib_table.clone()),
+        ("fp-lib-table", config.fp_lib_table.clone()),
+    ]
+}
+
+// snapshot directories, most recent first
+fn snapshots() -> io::Result<Vec<PathBuf>> {
+    let mut snapshots = match fs::read_dir(history_path()) {
+        Ok(entries) => entries
+            .map(|res| res.map(|e| e.path()))
+            .collect::<Result<Vec<_>, io::Error>>()?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    snapshots.retain(|x| x.is_dir());
+    snapshots.sort();
+    snapshots.reverse();
+
+    Ok(snapshots)
+}
+
+/// Backup of installed.ron and both lib tables taken before a mutating operation. When
+/// dropped after the operation, it is only kept if the operation changed one of them, so
+/// failed and no-op commands leave no history to undo.
+pub struct Snapshot {
+    path: PathBuf,
+    files: Vec<(&'static str, String)>,
+}
+
+/// Back up installed.ron and both lib tables before a mutating operation.
+pub fn snapshot(config: &Config, operation: &str) -> Result<Snapshot, Box<dyn error::Error>> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let snapshot_path = history_path().join(format!("{:015}", timestamp));
+    fs::create_dir_all(&snapshot_path)?;
+    let snapshot = Snapshot {
+        path: snapshot_path,
+        files: tracked_files(config),
+    };
+
+    for (name, path) in snapshot.files.iter() {
+        match fs::copy(path, snapshot.path.join(name)) {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+    write_atomic(snapshot.path.join("operation"), operation.as_bytes())?;
+
+    Ok(snapshot)
+}
+
+// contents of a file, None if it doesn't exist
+fn read_optional(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(x) => Ok(Some(x)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// whether two versions of a tracked file differ, installed.ron is compared by its values as
+// its libraries are written in hash map order
+fn differs(name: &str, before: &Option<Vec<u8>>, after: &Option<Vec<u8>>) -> bool {
+    if let ("installed.ron", Some(before), Some(after)) = (name, before, after) {
+        let parse = |x: &[u8]| ron::de::from_bytes::<ron::Value>(x).ok();
+        if let (Some(before), Some(after)) = (parse(before), parse(after)) {
+            return before != after;
+        }
+    }
+    before != after
+}
+
+impl Snapshot {
+    // whether a tracked file differs from its backup
+    fn changed(&self) -> io::Result<bool> {
+        for (name, path) in self.files.iter() {
+            let before = read_optional(&self.path.join(name))?;
+            if differs(name, &before, &read_optional(Path::new(path))?) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        if !self.changed().unwrap_or(true) {
+            let _ = fs::remove_dir_all(&self.path);
+            return;
+        }
+
+        // drop the oldest snapshots
+        if let Ok(snapshots) = snapshots() {
+            for old in snapshots.iter().skip(HISTORY_LIMIT) {
+                let _ = fs::remove_dir_all(old);
+            }
+        }
+    }
+}
+
+/// List the recorded operations, most recent first.
+pub fn history() -> Result<(), Box<dyn error::Error>> {
+    let snapshots = snapshots()?;
+    if snapshots.is_empty() {
+        println!("No operations recorded.");
+    }
+
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        let operation = fs::read_to_string(snapshot.join("operation")).unwrap_or_default();
+        let millis: i64 = snapshot
+            .file_name()
+            .and_then(|x| x.to_str())
+            .and_then(|x| x.parse().ok())
+            .unwrap_or_default();
+        let time = match Local.timestamp_millis_opt(millis).single() {
+            Some(x) => x.format("%Y-%m-%d %H:%M:%S").to_string(),
+            None => "unknown time".to_owned(),
+        };
+        println!("{:3}: {}  {}", i + 1, time, operation);
+    }
+
+    Ok(())
+}
+
+/// Restore installed.ron and both lib tables to the state before the last count operations.
+pub fn undo(config: &Config, count: usize, dry_run: bool) -> Result<(), Box<dyn error::Error>> {
+    let snapshots = snapshots()?;
+    if count == 0 || count > snapshots.len() {
+        return Err(Box::new(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Only {} operations recorded.", snapshots.len()),
+        )));
+    }
+
+    let target = &snapshots[count - 1];
+    if dry_run {
+        for (name, path) in tracked_files(config) {
+            if target.join(name).exists() {
+                println!("Would restore {}", path);
+            } else if Path::new(&path).exists() {
+                println!("Would remove {}", path);
+            }
+        }
+        for snapshot in snapshots.iter().take(count) {
+            let operation = fs::read_to_string(snapshot.join("operation")).unwrap_or_default();
+            println!("Would undo: {}", operation);
+        }
+        return Ok(());
+    }
+
+    for (name, path) in tracked_files(config) {
+        let backup = target.join(name);
+        if backup.exists() {
+            println!("Restoring {}...", path);
+            write_atomic(&path, &fs::read(backup)?)?;
+        } else if Path::new(&path).exists() {
+            // the file did not exist before the operation
+            println!("Removing {}...", path);
+            fs::remove_file(&path)?;
+        }
+    }
+
+    // the undone operations can not be undone again
+    for snapshot in snapshots.iter().take(count) {
+        let operation = fs::read_to_string(snapshot.join("operation")).unwrap_or_default();
+        println!("Undid: {}", operation);
+        fs::remove_dir_all(snapshot)?;
+    }
+    println!("Library files are not restored, run 'kibrarian doctor' to check them.");
+
+    Ok(())
+}
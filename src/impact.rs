@@ -0,0 +1,205 @@
+use crate::checksum;
+use crate::config::Config;
+use crate::libraries::{preview_source, Library};
+use crate::scan::References;
+use crate::sexpr;
+use crate::staging::staged_path;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::{error, fs};
+
+/// Symbols and footprints of a library as Nickname:Name, each with its definition.
+#[derive(Default)]
+pub struct Contents {
+    pub symbols: BTreeMap<String, String>,
+    pub footprints: BTreeMap<String, String>,
+}
+
+impl Contents {
+    /// Contents of the lib-table rows of an installed library, rows that can't be read are
+    /// skipped.
+    pub fn installed(library: &Library) -> Contents {
+        let mut contents = Contents::default();
+        for row in library.sym_lib_rows.iter() {
+            let _ = contents.add_symbols(&row.name, Path::new(&row.uri));
+        }
+        for row in library.fp_lib_rows.iter() {
+            let _ = contents.add_footprints(&row.name, Path::new(&row.uri));
+        }
+        contents
+    }
+
+    /// Contents of the library files in a source directory of a library.
+    pub fn source(library: &Library, root: &Path) -> Result<Contents, Box<dyn error::Error>> {
+        let mut contents = Contents::default();
+        let (sym_files, fp_files) = library.provided_files(root)?;
+        for (path, nickname) in sym_files.iter() {
+            contents.add_symbols(nickname, path)?;
+        }
+        for (path, nickname) in fp_files.iter() {
+            contents.add_footprints(nickname, path)?;
+        }
+        Ok(contents)
+    }
+
+    // symbols of a KiCad 6 .kicad_sym or legacy .lib file
+    fn add_symbols(&mut self, nickname: &str, path: &Path) -> Result<(), Box<dyn error::Error>> {
+        let text = fs::read_to_string(path)?;
+        if path.extension().is_some_and(|x| x == "lib") {
+            // DEF R R 0 0 N Y 1 F N ... ENDDEF
+            let mut current: Option<(String, String)> = None;
+            for line in text.lines() {
+                if let Some(definition) = line.strip_prefix("DEF ") {
+                    let name = definition.split_whitespace().next().unwrap_or_default();
+                    current = Some((name.trim_start_matches('~').to_owned(), String::new()));
+                }
+                if let Some((name, body)) = current.as_mut() {
+                    body.push_str(line);
+                    body.push('\n');
+                    if line.starts_with("ENDDEF") {
+                        self.symbols
+                            .insert(format!("{}:{}", nickname, name), body.clone());
+                        current = None;
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        for root in sexpr::parse(&text)?.iter() {
+            if let sexpr::Sexpr::List(items) = root {
+                for symbol in items.iter().filter(|x| x.head() == Some("symbol")) {
+                    if let sexpr::Sexpr::List(parts) = symbol {
+                        if let Some(name) = parts.get(1).and_then(|x| x.atom()) {
+                            self.symbols
+                                .insert(format!("{}:{}", nickname, name), symbol.to_string());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // footprints of a .pretty directory, one .kicad_mod file each
+    fn add_footprints(&mut self, nickname: &str, path: &Path) -> Result<(), Box<dyn error::Error>> {
+        for file in checksum::walk(path)? {
+            if file.extension().is_some_and(|x| x == "kicad_mod") {
+                let name = file.file_stem().unwrap_or_default().to_string_lossy();
+                self.footprints
+                    .insert(format!("{}:{}", nickname, name), fs::read_to_string(&file)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Symbols and footprints used by the projects in config.ron that a change to a library
+/// removes or changes, with the files using each.
+pub struct Impact {
+    removed: Vec<(String, BTreeSet<PathBuf>)>,
+    changed: Vec<(String, BTreeSet<PathBuf>)>,
+}
+
+impl Impact {
+    /// Compare the contents of a library before and after a change against the references
+    /// of the projects in config.ron.
+    pub fn analyze(
+        config: &Config,
+        old: &Contents,
+        new: &Contents,
+    ) -> Result<Impact, Box<dyn error::Error>> {
+        let mut references = References::default();
+        for project in config.projects.iter() {
+            let project_references = References::of_project(Path::new(project))?;
+            for (reference, files) in project_references.symbols {
+                references
+                    .symbols
+                    .entry(reference)
+                    .or_default()
+                    .extend(files);
+            }
+            for (reference, files) in project_references.footprints {
+                references
+                    .footprints
+                    .entry(reference)
+                    .or_default()
+                    .extend(files);
+            }
+        }
+
+        let mut impact = Impact {
+            removed: Vec::new(),
+            changed: Vec::new(),
+        };
+        for (old, new, used) in [
+            (&old.symbols, &new.symbols, &references.symbols),
+            (&old.footprints, &new.footprints, &references.footprints),
+        ]
+        .iter()
+        {
+            for (name, definition) in old.iter() {
+                let files = match used.get(name) {
+                    Some(x) => x.clone(),
+                    None => continue,
+                };
+                match new.get(name) {
+                    None => impact.removed.push((name.clone(), files)),
+                    Some(x) if x != definition => impact.changed.push((name.clone(), files)),
+                    Some(_) => {}
+                }
+            }
+        }
+
+        Ok(impact)
+    }
+
+    /// Impact of updating an installed library to a library planned from the index, whose
+    /// new files are checked out from its clone, left untouched by a dry run.
+    pub fn of_update(
+        config: &Config,
+        old: &Contents,
+        installed: &Library,
+        library: &Library,
+        dry_run: bool,
+    ) -> Result<Impact, Box<dyn error::Error>> {
+        // the installation keeps its selection and configured nickname prefix
+        let mut library = library.clone();
+        library.selection = installed.selection.clone();
+        if let Some(prefix) = config.nickname_prefixes.get(&library.name) {
+            library.nickname_prefix = Some(prefix.clone());
+        }
+
+        // the new files are checked out next to the staged clone, which stays for the install
+        let preview = staged_path(&format!("{}@preview", library.name));
+        if preview.exists() {
+            fs::remove_dir_all(&preview)?;
+        }
+        let new = preview_source(config, &library, &preview.to_string_lossy(), dry_run)
+            .and_then(|()| Contents::source(&library, &preview));
+        if preview.exists() {
+            fs::remove_dir_all(&preview)?;
+        }
+        Impact::analyze(config, old, &new?)
+    }
+
+    /// Removed symbols and footprints break projects, changed ones only need a review.
+    pub fn is_blocking(&self) -> bool {
+        !self.removed.is_empty()
+    }
+
+    /// Print the removed and changed symbols and footprints used by projects, if any.
+    pub fn print(&self, action: &str, name: &str) {
+        if self.removed.is_empty() && self.changed.is_empty() {
+            return;
+        }
+
+        println!("{} {} affects projects:", action, name);
+        for (kind, items) in [("removes", &self.removed), ("changes", &self.changed)].iter() {
+            for (item, files) in items.iter() {
+                let files: Vec<String> = files.iter().map(|x| x.display().to_string()).collect();
+                println!("  {} {}, used in {}", kind, item, files.join(", "));
+            }
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use crate::history::write_atomic;
+use crate::sexpr::{self, Sexpr};
+use serde::{Deserialize, Serialize};
+use std::{error, fmt, fs, io};
+
+/// A `(lib ...)` row of a KiCad sym-lib-table or fp-lib-table.
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct LibTableRow {
+    pub name: String,
+    pub lib_type: String,
+    pub uri: String,
+    pub options: String,
+    pub descr: String,
+    // the row as read from its table, fields such as (disabled) and (hidden) are written
+    // back from it
+    #[serde(skip)]
+    original: Option<Sexpr>,
+}
+
+// rows are the same library whatever KiCad keeps besides the written fields
+impl PartialEq for LibTableRow {
+    fn eq(&self, other: &LibTableRow) -> bool {
+        self.name == other.name
+            && self.lib_type == other.lib_type
+            && self.uri == other.uri
+            && self.options == other.options
+            && self.descr == other.descr
+    }
+}
+
+impl LibTableRow {
+    pub fn new(name: &str, lib_type: &str, uri: &str, descr: &str) -> LibTableRow {
+        LibTableRow {
+            name: name.to_owned(),
+            lib_type: lib_type.to_owned(),
+            uri: uri.to_owned(),
+            options: String::new(),
+            descr: descr.to_owned(),
+            original: None,
+        }
+    }
+
+    fn from_sexpr(lib: &Sexpr) -> Option<LibTableRow> {
+        Some(LibTableRow {
+            name: lib.value("name")?.to_owned(),
+            lib_type: lib.value("type").unwrap_or_default().to_owned(),
+            uri: lib.value("uri").unwrap_or_default().to_owned(),
+            options: lib.value("options").unwrap_or_default().to_owned(),
+            descr: lib.value("descr").unwrap_or_default().to_owned(),
+            original: Some(lib.clone()),
+        })
+    }
+
+    // the original row with the written fields replaced, fields it lacks are appended
+    fn to_sexpr(&self) -> Sexpr {
+        let mut items = match &self.original {
+            Some(Sexpr::List(items)) => items.clone(),
+            _ => vec![Sexpr::Atom("lib".to_owned())],
+        };
+
+        for (key, value) in [
+            ("name", &self.name),
+            ("type", &self.lib_type),
+            ("uri", &self.uri),
+            ("options", &self.options),
+            ("descr", &self.descr),
+        ]
+        .iter()
+        {
+            let pair = Sexpr::List(vec![
+                Sexpr::Atom((*key).to_owned()),
+                Sexpr::Atom((*value).to_owned()),
+            ]);
+            match items.iter_mut().find(|x| x.head() == Some(key)) {
+                Some(item) => *item = pair,
+                None => items.push(pair),
+            }
+        }
+
+        Sexpr::List(items)
+    }
+}
+
+impl fmt::Display for LibTableRow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_sexpr())
+    }
+}
+
+/// A KiCad library table, rows are kept in file order.
+#[derive(Debug)]
+pub struct LibTable {
+    kind: String,
+    other: Vec<Sexpr>,
+    pub rows: Vec<LibTableRow>,
+}
+
+impl LibTable {
+    /// Load a table of the given kind (`sym_lib_table` or `fp_lib_table`), a missing file is empty.
+    pub fn load(path: &str, kind: &str) -> Result<LibTable, Box<dyn error::Error>> {
+        let text = match fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(Box::new(e)),
+        };
+        LibTable::parse(&text, kind)
+    }
+
+    // a table of the given kind from the text of a lib-table file
+    fn parse(text: &str, kind: &str) -> Result<LibTable, Box<dyn error::Error>> {
+        let mut table = LibTable {
+            kind: kind.to_owned(),
+            other: Vec::new(),
+            rows: Vec::new(),
+        };
+
+        for root in sexpr::parse(text)?.iter() {
+            if let Sexpr::List(items) = root {
+                if let Some(head) = root.head() {
+                    table.kind = head.to_owned();
+                }
+                for item in items.iter().skip(1) {
+                    if item.head() == Some("lib") {
+                        if let Some(row) = LibTableRow::from_sexpr(item) {
+                            table.rows.push(row);
+                        }
+                    } else {
+                        table.other.push(item.clone());
+                    }
+                }
+            }
+        }
+
+        Ok(table)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        write_atomic(path, self.to_string().as_bytes())
+    }
+
+    pub fn find(&self, name: &str) -> Option<&LibTableRow> {
+        self.rows.iter().find(|x| x.name == name)
+    }
+
+    /// Add a row, returns false and leaves the table unchanged if the nickname is taken.
+    pub fn add(&mut self, row: LibTableRow) -> bool {
+        if self.find(&row.name).is_some() {
+            return false;
+        }
+        self.rows.push(row);
+        true
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<LibTableRow> {
+        let index = self.rows.iter().position(|x| x.name == name)?;
+        Some(self.rows.remove(index))
+    }
+}
+
+impl fmt::Display for LibTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "({}", self.kind)?;
+        for other in self.other.iter() {
+            writeln!(f, "  {}", other)?;
+        }
+        for row in self.rows.iter() {
+            writeln!(f, "  {}", row)?;
+        }
+        writeln!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LibTable, LibTableRow};
+    use crate::sexpr;
+
+    // an fp-lib-table as written by KiCad 8
+    const TABLE: &str = r#"(fp_lib_table
+  (version 7)
+  (lib (name "Connector_Custom")(type "KiCad")(uri "${KICAD8_FOOTPRINT_DIR}/Connector_Custom.pretty")(options "")(descr "Custom connectors")(disabled))
+  (lib (name "Vendor")(type "KiCad")(uri "${KIPRJMOD}/Vendor.pretty")(options "")(descr "Vendor footprints")(hidden))
+  (lib (name "Future")(type "KiCad")(uri "/opt/future.pretty")(options "")(descr "")(unknown_field "kept"))
+)
+"#;
+
+    #[test]
+    fn round_trip_keeps_every_field() {
+        let table = LibTable::parse(TABLE, "fp_lib_table").unwrap();
+        assert_eq!(table.rows.len(), 3);
+        assert_eq!(
+            sexpr::parse(&table.to_string()).unwrap(),
+            sexpr::parse(TABLE).unwrap()
+        );
+    }
+
+    #[test]
+    fn changed_row_keeps_unwritten_fields() {
+        let mut table = LibTable::parse(TABLE, "fp_lib_table").unwrap();
+        let mut row = table.remove("Connector_Custom").unwrap();
+        row.uri = "/opt/Connector_Custom.pretty".to_owned();
+        table.rows.insert(0, row);
+
+        assert_eq!(
+            table.rows[0].to_string(),
+            "(lib (name Connector_Custom)(type KiCad)(uri /opt/Connector_Custom.pretty)\
+             (options \"\")(descr \"Custom connectors\")(disabled))"
+        );
+        assert!(table.to_string().contains("(hidden)"));
+        assert!(table.to_string().contains("(unknown_field kept)"));
+    }
+
+    #[test]
+    fn rows_compare_by_written_fields() {
+        let table = LibTable::parse(TABLE, "fp_lib_table").unwrap();
+        let row = LibTableRow::new(
+            "Vendor",
+            "KiCad",
+            "${KIPRJMOD}/Vendor.pretty",
+            "Vendor footprints",
+        );
+        assert_eq!(table.find("Vendor"), Some(&row));
+        assert_eq!(
+            row.to_string(),
+            "(lib (name Vendor)(type KiCad)(uri ${KIPRJMOD}/Vendor.pretty)(options \"\")\
+             (descr \"Vendor footprints\"))"
+        );
+    }
+}
@@ -1,17 +1,44 @@
+use crate::archive;
+use crate::batch::Summary;
+use crate::cache;
+use crate::checksum;
 use crate::config::Config;
-use crate::git::clone;
+use crate::dependencies;
+use crate::git::{self, clone, CloneOptions};
+use crate::history::write_atomic;
+use crate::impact::{Contents, Impact};
+use crate::lib_table::{LibTable, LibTableRow};
+use crate::staging::{self, staged_path, Staging};
 use fs_extra::dir;
+use glob::Pattern;
 use ron::de::from_reader;
 use ron::ser;
+use semver::Version;
 use serde::{Deserialize, Serialize};
-use std::io::Write;
-use std::{collections::HashMap, error, ffi::OsStr, fmt, fs, io};
+use std::path::{Path, PathBuf};
+use std::{collections::HashMap, env, error, ffi::OsStr, fmt, fs, io, process};
+
+/// Symbol and footprint library files of a library, each with its nickname.
+pub type ProvidedFiles = (Vec<(PathBuf, String)>, Vec<(PathBuf, String)>);
+
+// extensions of symbol and footprint library files
+const SYMBOL_EXTENSIONS: [&str; 3] = ["lib", "dcm", "kicad_sym"];
+const FOOTPRINT_EXTENSIONS: [&str; 1] = ["pretty"];
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum LibraryError {
     LibraryNotFoundError,
     LibraryInstalledError,
     LibraryNotInstalledError,
+    LibraryNicknameError,
+    LibraryDependencyError(String),
+    LibraryRequiredError(Vec<String>),
+    LibraryNotCachedError,
+    LibraryImpactError(Vec<String>),
+    LibraryNotGitError,
+    LibraryHistoryError,
+    LibraryScopeError,
 }
 
 impl fmt::Display for LibraryError {
@@ -20,6 +47,38 @@ impl fmt::Display for LibraryError {
             LibraryError::LibraryNotFoundError => write!(f, "Library not found."),
             LibraryError::LibraryInstalledError => write!(f, "Library already installed."),
             LibraryError::LibraryNotInstalledError => write!(f, "Library is not installed."),
+            LibraryError::LibraryNicknameError => write!(
+                f,
+                "Library nicknames conflict with existing lib-table rows, set a nickname prefix or mapping."
+            ),
+            LibraryError::LibraryDependencyError(reason) => {
+                write!(f, "Can't resolve dependencies: {}.", reason)
+            }
+            LibraryError::LibraryRequiredError(names) => write!(
+                f,
+                "Library is required by {}, use --force to uninstall it anyway.",
+                names.join(", ")
+            ),
+            LibraryError::LibraryNotCachedError => {
+                write!(f, "Library is not in the cache, it can't be installed offline.")
+            }
+            LibraryError::LibraryImpactError(names) => write!(
+                f,
+                "Projects use symbols or footprints that would be removed from {}, use --force to proceed anyway.",
+                names.join(", ")
+            ),
+            LibraryError::LibraryNotGitError => write!(
+                f,
+                "Library is not a git repository, its revisions can't be compared."
+            ),
+            LibraryError::LibraryHistoryError => write!(
+                f,
+                "Library has no earlier commit to roll back to, use --to to choose a revision."
+            ),
+            LibraryError::LibraryScopeError => write!(
+                f,
+                "Only global installations are implemented, use -g to reinstall global libraries."
+            ),
         }
     }
 }
@@ -30,6 +89,16 @@ impl error::Error for LibraryError {
             LibraryError::LibraryNotFoundError => "Library not found",
             LibraryError::LibraryInstalledError => "Library already installed.",
             LibraryError::LibraryNotInstalledError => "Library is not installed.",
+            LibraryError::LibraryNicknameError => {
+                "Library nicknames conflict with existing lib-table rows."
+            }
+            LibraryError::LibraryDependencyError(_) => "Can't resolve dependencies.",
+            LibraryError::LibraryRequiredError(_) => "Library is required by other libraries.",
+            LibraryError::LibraryNotCachedError => "Library is not in the cache.",
+            LibraryError::LibraryImpactError(_) => "Library is used by projects.",
+            LibraryError::LibraryNotGitError => "Library is not a git repository.",
+            LibraryError::LibraryHistoryError => "Library has no earlier commit.",
+            LibraryError::LibraryScopeError => "Only global installations are implemented.",
         }
     }
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
@@ -39,7 +108,7 @@ impl error::Error for LibraryError {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Libraries {
-    lib_map: HashMap<String, Library>,
+    pub lib_map: HashMap<String, Library>,
 }
 
 impl Libraries {
@@ -60,6 +129,243 @@ impl fmt::Display for Libraries {
     }
 }
 
+/// Where the files of a library come from.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Serialize)]
+pub enum Source {
+    #[default]
+    Git,
+    Path,
+    Archive,
+}
+
+/// How installed library files refer to the library source in ~/.kibrarian/extra.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Serialize)]
+pub enum InstallMode {
+    /// Copy files into ~/.kibrarian/libraries.
+    #[default]
+    Copy,
+    /// Symlink files in ~/.kibrarian/libraries to the source.
+    Symlink,
+    /// Hardlink files in ~/.kibrarian/libraries to the source.
+    Hardlink,
+    /// Point lib-table rows directly at the source.
+    Direct,
+}
+
+impl InstallMode {
+    /// Whether installed files change together with the library source.
+    pub fn tracks_source(self) -> bool {
+        self == InstallMode::Symlink || self == InstallMode::Direct
+    }
+}
+
+impl std::str::FromStr for InstallMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<InstallMode, String> {
+        match s {
+            "copy" => Ok(InstallMode::Copy),
+            "symlink" => Ok(InstallMode::Symlink),
+            "hardlink" => Ok(InstallMode::Hardlink),
+            "direct" => Ok(InstallMode::Direct),
+            _ => Err(format!("Unknown install mode: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for InstallMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InstallMode::Copy => write!(f, "copy"),
+            InstallMode::Symlink => write!(f, "symlink"),
+            InstallMode::Hardlink => write!(f, "hardlink"),
+            InstallMode::Direct => write!(f, "direct"),
+        }
+    }
+}
+
+/// Directory structure of installed symbol and footprint libraries found in subdirectories.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Serialize)]
+pub enum Layout {
+    /// Install all library files directly in the library directory.
+    #[default]
+    Flatten,
+    /// Keep the subdirectories below the declared path.
+    Preserve,
+}
+
+/// Symbol and footprint libraries of a library to install, matched by file stem against
+/// glob patterns. An empty include list selects everything.
+#[derive(Debug, Default, Deserialize, Clone, PartialEq, Serialize)]
+pub struct Selection {
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl Selection {
+    pub fn matches(&self, name: &str) -> bool {
+        let matches = |patterns: &Vec<String>| {
+            patterns.iter().any(|x| match Pattern::new(x) {
+                Ok(pattern) => pattern.matches(name),
+                Err(_) => x == name,
+            })
+        };
+
+        (self.include.is_empty() || matches(&self.include)) && !matches(&self.exclude)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+}
+
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.include.is_empty() {
+            write!(f, "only: {}", self.include.join(", "))?;
+        }
+        if !self.exclude.is_empty() {
+            if !self.include.is_empty() {
+                write!(f, "\t")?;
+            }
+            write!(f, "exclude: {}", self.exclude.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Options for uninstalling a library.
+#[derive(Clone)]
+pub struct UninstallOptions {
+    force: bool,
+    dry_run: bool,
+    removed: Vec<String>,
+}
+
+impl UninstallOptions {
+    pub fn new() -> UninstallOptions {
+        UninstallOptions {
+            force: false,
+            dry_run: false,
+            removed: Vec::new(),
+        }
+    }
+
+    /// Uninstall even if other installed libraries depend on the library.
+    pub fn force(&mut self, force: bool) {
+        self.force = force;
+    }
+
+    /// Report what would be removed without changing anything.
+    pub fn dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Treat a library an earlier uninstall of the same batch removed as uninstalled, which
+    /// a dry run left in installed.ron.
+    pub fn removed(&mut self, name: &str) {
+        self.removed.push(name.to_owned());
+    }
+}
+
+/// Another library from the index a library needs, optionally restricted to versions
+/// matching a semver requirement such as `^1.2`.
+#[derive(Debug, Deserialize, Clone, PartialEq, Serialize)]
+pub struct Dependency {
+    pub name: String,
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{} {}", self.name, version),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Options for installing a library, unset options fall back to config.ron.
+#[derive(Clone)]
+pub struct InstallOptions {
+    mode: Option<InstallMode>,
+    selection: Option<Selection>,
+    dependency: bool,
+    version: Option<String>,
+    revision: Option<String>,
+    dry_run: bool,
+    replace: bool,
+    commits: Vec<String>,
+}
+
+impl InstallOptions {
+    pub fn new() -> InstallOptions {
+        InstallOptions {
+            mode: None,
+            selection: None,
+            dependency: false,
+            version: None,
+            revision: None,
+            dry_run: false,
+            replace: false,
+            commits: Vec::new(),
+        }
+    }
+
+    /// Report what would be installed without changing anything.
+    pub fn dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    // install over an installed library, only used to preview updates
+    fn replace(&mut self, replace: bool) {
+        self.replace = replace;
+    }
+
+    // commits of the installation a reinstall replaces, the new commit is recorded after them
+    fn commits(&mut self, commits: Vec<String>) {
+        self.commits = commits;
+    }
+
+    /// Install the highest version matching a semver requirement such as `^2.1`.
+    pub fn version(&mut self, version: &str) {
+        self.version = Some(version.to_owned());
+    }
+
+    /// Install a revision instead of the one in libraries.ron or a resolved version.
+    pub fn revision(&mut self, revision: String) {
+        self.revision = Some(revision);
+    }
+
+    /// Install the library only to satisfy other libraries, so autoremove can remove it.
+    pub fn dependency(&mut self, dependency: bool) {
+        self.dependency = dependency;
+    }
+
+    pub fn mode(&mut self, mode: InstallMode) {
+        self.mode = Some(mode);
+    }
+
+    /// Only install the symbol and footprint libraries matching the given names.
+    pub fn only(&mut self, names: &[&str]) {
+        self.selection = Some(Selection {
+            include: names.iter().map(|x| x.to_string()).collect(),
+            exclude: Vec::new(),
+        });
+    }
+
+    pub fn selection(&mut self, selection: Selection) {
+        self.selection = Some(selection);
+    }
+}
+
 #[derive(Debug, Deserialize, Clone, Serialize)]
 pub struct Library {
     pub name: String,
@@ -67,6 +373,193 @@ pub struct Library {
     pub url: String,
     pub symbols_path: String,
     pub footprints_path: String,
+    #[serde(default)]
+    pub additional_symbols_paths: Vec<String>,
+    #[serde(default)]
+    pub additional_footprints_paths: Vec<String>,
+    #[serde(default)]
+    pub layout: Layout,
+    #[serde(default)]
+    pub models_path: Option<String>,
+    #[serde(default)]
+    pub revision: Option<String>,
+    #[serde(default)]
+    pub source: Source,
+    #[serde(default)]
+    pub link: bool,
+    #[serde(default)]
+    pub mode: InstallMode,
+    #[serde(default)]
+    pub selection: Selection,
+    #[serde(default)]
+    pub dependencies: Vec<Dependency>,
+    #[serde(default)]
+    pub dependency: bool,
+    #[serde(default)]
+    pub version_requirement: Option<String>,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub nickname_prefix: Option<String>,
+    #[serde(default)]
+    pub nicknames: HashMap<String, String>,
+    #[serde(default)]
+    pub adopted: bool,
+    #[serde(default)]
+    pub manifest: HashMap<String, String>,
+    #[serde(default)]
+    pub sym_lib_rows: Vec<LibTableRow>,
+    #[serde(default)]
+    pub fp_lib_rows: Vec<LibTableRow>,
+    #[serde(default)]
+    pub commits: Vec<String>,
+}
+
+impl Library {
+    /// Clone options fetching only the pinned revision and the declared library paths.
+    pub fn clone_options(&self, full_fallback: bool) -> CloneOptions {
+        let mut options = CloneOptions::new();
+        if let Some(revision) = &self.revision {
+            options.revision(revision);
+        }
+        for path in self.paths() {
+            options.path(path);
+        }
+        options.full_fallback(full_fallback);
+        options
+    }
+
+    /// Lib-table nickname of a symbol or footprint library file, from the explicit
+    /// nickname mapping or the file stem with the nickname prefix.
+    pub fn nickname(&self, stem: &str) -> String {
+        if let Some(nickname) = self.nicknames.get(stem) {
+            nickname.clone()
+        } else if let Some(prefix) = &self.nickname_prefix {
+            format!("{}_{}", prefix, stem)
+        } else {
+            stem.to_owned()
+        }
+    }
+
+    /// Selected symbol and footprint library files in a source directory of the library, each
+    /// with its nickname.
+    pub fn provided_files(&self, root: &Path) -> Result<ProvidedFiles, Box<dyn error::Error>> {
+        let select = |files: Vec<(PathBuf, PathBuf)>, extensions: &[&str]| {
+            files
+                .into_iter()
+                .map(|(p, _)| p)
+                .filter(|p| has_extension(p, extensions))
+                .filter(|p| self.selection.matches(&file_stem(p)))
+                .map(|p| {
+                    let nickname = self.nickname(&file_stem(&p));
+                    (p, nickname)
+                })
+                .collect()
+        };
+
+        Ok((
+            select(
+                discover(root, &self.symbols_paths(), &SYMBOL_EXTENSIONS)?,
+                &["lib", "kicad_sym"],
+            ),
+            select(
+                discover(root, &self.footprints_paths(), &FOOTPRINT_EXTENSIONS)?,
+                &["pretty"],
+            ),
+        ))
+    }
+
+    /// Declared symbol paths inside the library source, which may contain glob patterns.
+    pub fn symbols_paths(&self) -> Vec<&str> {
+        let mut paths = vec![&self.symbols_path[..]];
+        paths.extend(self.additional_symbols_paths.iter().map(|x| &x[..]));
+        paths
+    }
+
+    /// Declared footprint paths inside the library source, which may contain glob patterns.
+    pub fn footprints_paths(&self) -> Vec<&str> {
+        let mut paths = vec![&self.footprints_path[..]];
+        paths.extend(self.additional_footprints_paths.iter().map(|x| &x[..]));
+        paths
+    }
+
+    /// Declared symbol, footprint and model paths inside the library source.
+    pub fn paths(&self) -> Vec<&str> {
+        let mut paths = self.symbols_paths();
+        paths.append(&mut self.footprints_paths());
+        if let Some(models_path) = &self.models_path {
+            paths.push(&models_path[..]);
+        }
+        paths
+    }
+
+    /// Semantic version of the pinned revision, ignoring a leading v.
+    pub fn version(&self) -> Option<Version> {
+        let revision = self.revision.as_ref()?;
+        Version::parse(revision.trim_start_matches('v')).ok()
+    }
+
+    /// Versions available for installation with the revision to install each, highest first.
+    /// Git libraries offer every semver tag of their remote, or of their cached clone when
+    /// offline, other libraries only their pinned revision.
+    pub fn versions(&self, config: &Config) -> Result<Vec<(Version, String)>, git2::Error> {
+        let mut versions = Vec::new();
+        if self.source == Source::Git && self.local_path().is_none() {
+            let tags = match cache::clone_path(&self.name) {
+                Some(path) if config.offline => git::local_tags(&path)?,
+                None if config.offline => Vec::new(),
+                _ => git::remote_tags(&config.rewrite_url(&self.url))?,
+            };
+            for tag in tags {
+                if let Ok(version) = Version::parse(tag.trim_start_matches('v')) {
+                    versions.push((version, tag));
+                }
+            }
+        } else if let (Some(version), Some(revision)) = (self.version(), &self.revision) {
+            versions.push((version, revision.clone()));
+        }
+        versions.sort();
+        versions.reverse();
+
+        Ok(versions)
+    }
+
+    /// Make a relative path url absolute, relative to the directory of the file listing the
+    /// library rather than the working directory.
+    fn resolve_url(&mut self, base: &Path) {
+        let relative = self.url.starts_with('.')
+            || (self.source == Source::Path
+                && !self.url.starts_with('/')
+                && !self.url.starts_with('~')
+                && !self.url.starts_with("file://"));
+        if relative {
+            let path = self.url.strip_prefix("./").unwrap_or(&self.url);
+            self.url = base.join(path).to_string_lossy().into_owned();
+        }
+    }
+
+    /// Local directory, or archive file, of the library if its url is a path or file:// url.
+    pub fn local_path(&self) -> Option<PathBuf> {
+        let path = if let Some(path) = self.url.strip_prefix("file://") {
+            path
+        } else if self.url.starts_with("http://") || self.url.starts_with("https://") {
+            return None;
+        } else if self.source == Source::Path
+            || self.url.starts_with('/')
+            || self.url.starts_with('.')
+            || self.url.starts_with('~')
+        {
+            &self.url[..]
+        } else {
+            return None;
+        };
+
+        if let Some(path) = path.strip_prefix("~/") {
+            Some(Path::new(env!("HOME")).join(path))
+        } else {
+            Some(PathBuf::from(path))
+        }
+    }
 }
 
 impl fmt::Display for Library {
@@ -74,15 +567,73 @@ impl fmt::Display for Library {
         write!(
             f,
             "[{}]: {}\tsyms: {}\tfps: {}",
-            self.name, self.url, self.symbols_path, self.footprints_path
-        )
+            self.name,
+            self.url,
+            self.symbols_paths().join(", "),
+            self.footprints_paths().join(", ")
+        )?;
+        if self.layout == Layout::Preserve {
+            write!(f, "\tpreserve layout")?;
+        }
+        if let Some(models_path) = &self.models_path {
+            write!(f, "\tmodels: {}", models_path)?;
+        }
+        if let Some(revision) = &self.revision {
+            write!(f, "\trev: {}", revision)?;
+        }
+        if let Some(version_requirement) = &self.version_requirement {
+            write!(f, "\tversion: {}", version_requirement)?;
+        }
+        if self.source == Source::Archive {
+            write!(f, "\tarchive")?;
+        }
+        if self.link {
+            write!(f, "\tlinked")?;
+        }
+        if self.mode != InstallMode::Copy {
+            write!(f, "\tmode: {}", self.mode)?;
+        }
+        if !self.selection.is_empty() {
+            write!(f, "\t{}", self.selection)?;
+        }
+        if !self.dependencies.is_empty() {
+            let dependencies: Vec<String> =
+                self.dependencies.iter().map(|x| x.to_string()).collect();
+            write!(f, "\tdepends: {}", dependencies.join(", "))?;
+        }
+        if self.dependency {
+            write!(f, "\tdependency")?;
+        }
+        if self.adopted {
+            write!(f, "\tadopted")?;
+        }
+        Ok(())
     }
 }
 
 pub fn get_libraries(library_path: String) -> Result<Libraries, ron::de::Error> {
     // read libraries from file
     let f = fs::File::open(&library_path).expect("Failed opening file.");
-    from_reader(f)
+    let mut libraries: Libraries = from_reader(f)?;
+
+    // relative path urls are relative to the directory of the file
+    let path = fs::canonicalize(&library_path).unwrap_or_else(|_| PathBuf::from(&library_path));
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    for library in libraries.lib_map.values_mut() {
+        library.resolve_url(base);
+    }
+    Ok(libraries)
+}
+
+pub fn save_libraries(
+    libraries: &Libraries,
+    library_path: String,
+) -> Result<(), Box<dyn error::Error>> {
+    // write libraries to a temporary file and move it into place
+    let serialized = ser::to_string(libraries)?;
+    write_atomic(library_path, serialized.as_bytes())?;
+
+    Ok(())
 }
 
 pub fn search(library_path: String, query: &str) -> Option<Library> {
@@ -105,54 +656,367 @@ pub fn search(library_path: String, query: &str) -> Option<Library> {
     }
 }
 
-pub fn install(config: Config, global: bool, query: &str) -> Result<(), Box<dyn error::Error>> {
-    if let Some(library) = search(config.libraries, query) {
-        // load installed libraries
+pub fn install(
+    config: Config,
+    global: bool,
+    query: &str,
+    options: &InstallOptions,
+) -> Result<(), Box<dyn error::Error>> {
+    let index = get_libraries(config.libraries.clone())?;
+    let installed_path = format!("{}/.config/kibrarian/installed.ron", env!("HOME"));
+    let mut installed_libraries = get_libraries(installed_path.clone())?;
+
+    // installing a library pulled in as a dependency keeps it from being autoremoved
+    match installed_libraries.lib_map.get_mut(query) {
+        Some(_) if options.replace => {}
+        Some(installed) if installed.dependency && !options.dependency => {
+            if options.dry_run {
+                println!("Would mark {} as explicitly installed.", query);
+                return Ok(());
+            }
+            println!("{} is now marked as explicitly installed.", query);
+            installed.dependency = false;
+            return save_libraries(&installed_libraries, installed_path);
+        }
+        Some(_) => return Err(Box::new(LibraryError::LibraryInstalledError)),
+        None => {}
+    }
+
+    // clone the library and its missing dependencies concurrently before installing them
+    let plan = plan(&index, &installed_libraries, query, options, &config)?;
+    let _staging = Staging::begin()?;
+    if !options.dry_run {
+        staging::stage(&config, &plan);
+    }
+
+    for library in plan {
+        if library.name == query {
+            let mut options = options.clone();
+            if let Some(revision) = library.revision {
+                options.revision(revision);
+            }
+            return install_library(config, global, query, &options);
+        }
+
+        println!("Installing dependency {}...", library.name);
+        let mut dependency_options = InstallOptions::new();
+        if let Some(mode) = options.mode {
+            dependency_options.mode(mode);
+        }
+        if let Some(revision) = library.revision {
+            dependency_options.revision(revision);
+        }
+        dependency_options.dependency(true);
+        dependency_options.dry_run(options.dry_run);
+        install_library(config.clone(), global, &library.name, &dependency_options)?;
+    }
+
+    Ok(())
+}
+
+/// Index entries of query and its missing dependencies in installation order, each at the
+/// highest revision meeting all version requirements, offline only versions in the cache.
+pub fn plan(
+    index: &Libraries,
+    installed_libraries: &Libraries,
+    query: &str,
+    options: &InstallOptions,
+    config: &Config,
+) -> Result<Vec<Library>, Box<dyn error::Error>> {
+    let requirement = match &options.version {
+        Some(x) => Some(dependencies::parse_requirement(query, x)?),
+        None => None,
+    };
+
+    let mut plan = Vec::new();
+    for (name, requirements) in
+        dependencies::resolve(index, installed_libraries, query, requirement)?
+    {
+        if name != query && installed_libraries.lib_map.contains_key(&name) {
+            continue;
+        }
+
+        let mut library = index.lib_map[&name].clone();
+        if name == query && options.revision.is_some() {
+            library.revision = options.revision.clone();
+        } else if let Some(revision) =
+            dependencies::choose_version(&library, &requirements, config)?
+        {
+            library.revision = Some(revision);
+        }
+        plan.push(library);
+    }
+
+    Ok(plan)
+}
+
+/// Fetch the files of a library to destination, taking a staged or cached clone, extracting
+/// its archive, copying its local working tree or cloning it.
+pub fn fetch_source(
+    config: &Config,
+    library: &Library,
+    destination: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    let url = config.rewrite_url(&library.url);
+    if staged_path(&library.name).exists() {
+        fs::rename(staged_path(&library.name), destination)?;
+    } else if library.source == Source::Archive {
+        extract_archive(config, library, destination, true)?;
+    } else if let Some(local_path) = library.local_path() {
+        copy_local(library, &local_path, destination)?;
+    } else if !cache::restore(
+        &library.name,
+        &url,
+        Path::new(destination),
+        &library.clone_options(config.full_clone_fallback),
+        config.offline,
+    )? {
+        if config.offline {
+            return Err(Box::new(LibraryError::LibraryNotCachedError));
+        }
+        clone(
+            &url,
+            destination.to_owned(),
+            &library.clone_options(config.full_clone_fallback),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Extract the verified archive of a library to destination, read from its local path, the
+/// cache offline or downloaded, and kept in the cache if keep is set.
+fn extract_archive(
+    config: &Config,
+    library: &Library,
+    destination: &str,
+    keep: bool,
+) -> Result<(), Box<dyn error::Error>> {
+    let data = match library.local_path() {
+        Some(local_path) => fs::read(local_path)?,
+        None if config.offline => match fs::read(cache::archive_path(&library.name)) {
+            Ok(data) => data,
+            Err(_) => return Err(Box::new(LibraryError::LibraryNotCachedError)),
+        },
+        None => {
+            let data = archive::download(&config.rewrite_url(&library.url))?;
+            if keep {
+                cache::keep_archive(&library.name, &data)?;
+            }
+            data
+        }
+    };
+    archive::verify(&data, library.sha256.as_deref(), config.unverified_archives)?;
+    archive::extract(&data, destination)?;
+    Ok(())
+}
+
+/// Existing clone of a library a dry run can read, staged for an update, installed or cached.
+fn preview_clone(library: &Library) -> Option<PathBuf> {
+    if library.source != Source::Git || library.local_path().is_some() {
+        return None;
+    }
+    vec![staged_path(&library.name)]
+        .into_iter()
+        .chain(cache::clone_path(&library.name))
+        .find(|path| path.join(".git").is_dir())
+}
+
+/// Write the files of a library to destination like fetch_source, but check an existing clone
+/// out in place instead of moving it, so dry runs leave staged and cached clones alone. A
+/// pinned revision the clone has is not fetched, otherwise it is fetched into the clone, or
+/// in a dry run cloned into destination so the clone stays untouched. Archives downloaded by
+/// a dry run are not cached.
+pub fn preview_source(
+    config: &Config,
+    library: &Library,
+    destination: &str,
+    dry_run: bool,
+) -> Result<(), Box<dyn error::Error>> {
+    let url = config.rewrite_url(&library.url);
+    let options = library.clone_options(config.full_clone_fallback);
+    match preview_clone(library) {
+        Some(clone_path) => {
+            let revision = library.revision.as_deref().unwrap_or("HEAD");
+            let present =
+                library.revision.is_some() && git::commit_id(&clone_path, revision).is_ok();
+            if !present && !config.offline {
+                if dry_run {
+                    clone(&url, destination.to_owned(), &options)?;
+                    return Ok(());
+                }
+                git::prefetch(&clone_path, &url, &options)?;
+            }
+            git::checkout_to(&clone_path, revision, Path::new(destination))?;
+            Ok(())
+        }
+        None if library.source == Source::Git && library.local_path().is_none() => {
+            if config.offline {
+                return Err(Box::new(LibraryError::LibraryNotCachedError));
+            }
+            clone(&url, destination.to_owned(), &options)?;
+            Ok(())
+        }
+        None if library.source == Source::Archive => {
+            extract_archive(config, library, destination, !dry_run)
+        }
+        None => fetch_source(config, library, destination),
+    }
+}
+
+fn install_library(
+    config: Config,
+    global: bool,
+    query: &str,
+    options: &InstallOptions,
+) -> Result<(), Box<dyn error::Error>> {
+    if let Some(mut library) = search(config.libraries.clone(), query) {
+        // load installed libraries and lib tables
         let mut installed_libraries =
             get_libraries(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))?;
+        let mut sym_lib_table = LibTable::load(&config.sym_lib_table, "sym_lib_table")?;
+        let mut fp_lib_table = LibTable::load(&config.fp_lib_table, "fp_lib_table")?;
 
-        // check if already installed
-        if installed_libraries.lib_map.contains_key(&library.name[..]) {
-            return Err(Box::new(LibraryError::LibraryInstalledError));
+        // check if already installed, a previewed update replaces the installed library
+        let replaced = match installed_libraries.lib_map.remove(&library.name) {
+            Some(installed) if options.replace => {
+                for row in installed.sym_lib_rows.iter() {
+                    sym_lib_table.remove(&row.name);
+                }
+                for row in installed.fp_lib_rows.iter() {
+                    fp_lib_table.remove(&row.name);
+                }
+                Some(installed)
+            }
+            Some(_) => return Err(Box::new(LibraryError::LibraryInstalledError)),
+            None => None,
+        };
+
+        // linked local libraries track their working tree
+        library.mode = options.mode.unwrap_or(config.install_mode);
+        if library.link && library.mode == InstallMode::Copy {
+            library.mode = InstallMode::Symlink;
+        }
+
+        library.dependency = options.dependency;
+        library.version_requirement = options.version.clone();
+        if let Some(revision) = &options.revision {
+            library.revision = Some(revision.clone());
+        }
+
+        // a selection given on the command line or in config.ron replaces the one in libraries.ron
+        if let Some(selection) = &options.selection {
+            library.selection = selection.clone();
+        } else if let Some(selection) = config.selections.get(&library.name) {
+            library.selection = selection.clone();
+        }
+
+        // a prefix in config.ron takes precedence over the one in libraries.ron
+        if let Some(prefix) = config.nickname_prefixes.get(&library.name) {
+            library.nickname_prefix = Some(prefix.clone());
         }
 
-        // clone repository
-        let installation_path = format!("{}/.kibrarian/extra/{}", env!("HOME"), query);
-        clone(&library.url[..], installation_path.clone())?;
+        // rewrite rules in config.ron point remote urls to mirrors
+        let url = config.rewrite_url(&library.url);
+
+        // a dry run reads local libraries in place and fetches others to a temporary directory
+        let mut installation_path = format!("{}/.kibrarian/extra/{}", env!("HOME"), query);
+        let mut source_path = PathBuf::from(&installation_path);
+        if options.dry_run {
+            installation_path = env::temp_dir()
+                .join(format!("kibrarian-dry-run-{}-{}", process::id(), query))
+                .to_string_lossy()
+                .into_owned();
+            source_path = PathBuf::from(&installation_path);
+            match (library.source, library.local_path()) {
+                (Source::Archive, _) => println!("Would download {}", url),
+                (_, Some(local_path)) => {
+                    println!("Would copy from {}", local_path.display());
+                    source_path = local_path;
+                }
+                (_, None) => match (preview_clone(&library), &library.revision) {
+                    (Some(clone_path), _) => {
+                        println!("Would check out from the clone in {}", clone_path.display())
+                    }
+                    (None, _) if config.offline => {
+                        return Err(Box::new(LibraryError::LibraryNotCachedError))
+                    }
+                    (None, Some(revision)) => println!("Would clone {} at {}", url, revision),
+                    (None, None) => println!("Would clone {}", url),
+                },
+            }
+        }
+
+        if source_path == Path::new(&installation_path) {
+            if options.dry_run {
+                preview_source(&config, &library, &installation_path, true)?;
+            } else {
+                fetch_source(&config, &library, &installation_path)?;
+            }
+        }
 
-        // get vector of symbol library files
+        // find symbol and footprint library files below the declared paths
         let library_sym_files =
-            fs::read_dir(format!("{}/{}/", installation_path, library.symbols_path))?
-                .map(|res| res.map(|e| e.path()))
-                .collect::<Result<Vec<_>, io::Error>>()?;
-
-        // get vector of footprint library files
-        let library_fp_files = fs::read_dir(format!(
-            "{}/{}/",
-            installation_path, library.footprints_path
-        ))?
-        .map(|res| res.map(|e| e.path()))
-        .collect::<Result<Vec<_>, io::Error>>()?;
+            discover(&source_path, &library.symbols_paths(), &SYMBOL_EXTENSIONS)?;
+        let library_fp_files = discover(
+            &source_path,
+            &library.footprints_paths(),
+            &FOOTPRINT_EXTENSIONS,
+        )?;
 
-        if global {
-            // create library directories in global location
-            fs::create_dir(format!(
-                "{}/.kibrarian/libraries/symbols/{}",
-                env!("HOME"),
-                query
-            ))?;
-            fs::create_dir(format!(
-                "{}/.kibrarian/libraries/footprints/{}",
-                env!("HOME"),
-                query
-            ))?;
-        } else {
-            // TODO: create library directory in project
-            unimplemented!();
+        // check nicknames against existing lib-table rows before installing anything
+        let sym_nicknames = nicknames(&library, &library_sym_files, &["lib", "kicad_sym"]);
+        let fp_nicknames = nicknames(&library, &library_fp_files, &["pretty"]);
+        let sym_conflicts =
+            nickname_conflicts(&sym_lib_table, &sym_nicknames, &installed_libraries);
+        let fp_conflicts = nickname_conflicts(&fp_lib_table, &fp_nicknames, &installed_libraries);
+
+        if !sym_conflicts.is_empty() || !fp_conflicts.is_empty() {
+            for conflict in sym_conflicts.iter() {
+                println!("sym-lib-table: {}", conflict);
+            }
+            for conflict in fp_conflicts.iter() {
+                println!("fp-lib-table: {}", conflict);
+            }
+            if source_path == Path::new(&installation_path) {
+                remove_dir(&installation_path)?;
+            }
+            return Err(Box::new(LibraryError::LibraryNicknameError));
+        }
+
+        if let Some(replaced) = &replaced {
+            for directory in installed_directories(&replaced.name) {
+                println!(
+                    "Would replace {}/.kibrarian/libraries/{}",
+                    env!("HOME"),
+                    directory
+                );
+            }
         }
 
-        for p in library_sym_files.iter() {
-            // copy lib and dcm files to symbols library directory
+        if !options.dry_run {
+            if global {
+                // create library directories in global location
+                fs::create_dir(format!(
+                    "{}/.kibrarian/libraries/symbols/{}",
+                    env!("HOME"),
+                    query
+                ))?;
+                fs::create_dir(format!(
+                    "{}/.kibrarian/libraries/footprints/{}",
+                    env!("HOME"),
+                    query
+                ))?;
+            } else {
+                // TODO: create library directory in project
+                unimplemented!();
+            }
+        }
+
+        let descr = format!("Installed by kibrarian ({})", library.name);
+
+        for (p, subdirectory) in library_sym_files.iter() {
+            // copy lib, dcm and kicad_sym files to symbols library directory
             let file_osstr = match p.file_name() {
                 Some(x) => x,
                 None => continue,
@@ -169,19 +1033,41 @@ pub fn install(config: Config, global: bool, query: &str) -> Result<(), Box<dyn
                 "./libraries/".to_owned()
             };
 
-            if p.extension() == Some(OsStr::new("lib")) || p.extension() == Some(OsStr::new("dcm"))
-            {
-                destination.push_str(&format!("symbols/{}/{}", query, filename)[..]);
-            } else {
+            if !library.selection.matches(&file_stem(p)) {
                 continue;
             }
 
-            fs::copy(p, destination)?;
+            destination.push_str(&format!("symbols/{}/", query));
+            destination.push_str(&layout_path(library.layout, subdirectory, filename));
+            if options.dry_run {
+                println!("Would install {}", destination);
+            } else {
+                create_parent(&destination, library.mode)?;
+                install_path(p, &destination, library.mode)?;
+            }
 
-            // TODO: Add entry to sym-lib-table
+            // add entry to sym-lib-table
+            let lib_type = match p.extension().and_then(|x| x.to_str()) {
+                Some("lib") => "Legacy",
+                Some("kicad_sym") => "KiCad",
+                _ => continue,
+            };
+            let nickname = library.nickname(&file_stem(p));
+            let uri = table_uri(p, &destination, library.mode);
+            let row = LibTableRow::new(&nickname, lib_type, &uri, &descr);
+            if add_row(&mut sym_lib_table, row.clone(), "sym-lib-table") {
+                if options.dry_run {
+                    report_row(
+                        "sym-lib-table",
+                        &row,
+                        replaced.as_ref().map(|x| &x.sym_lib_rows),
+                    );
+                }
+                library.sym_lib_rows.push(row);
+            }
         }
 
-        for p in library_fp_files.iter() {
+        for (p, subdirectory) in library_fp_files.iter() {
             // copy pretty directories to library directory
             let file_osstr = match p.file_name() {
                 Some(x) => x,
@@ -199,41 +1085,90 @@ pub fn install(config: Config, global: bool, query: &str) -> Result<(), Box<dyn
                 "./libraries/".to_owned()
             };
 
-            if p.extension() == Some(OsStr::new("pretty")) {
-                destination.push_str(&format!("footprints/{}/{}", query, filename)[..]);
-            } else {
+            if !library.selection.matches(&file_stem(p)) {
                 continue;
             }
 
-            let mut options = dir::CopyOptions::new();
-            options.copy_inside = true;
-            dir::copy(p, destination, &options)?;
+            destination.push_str(&format!("footprints/{}/", query));
+            destination.push_str(&layout_path(library.layout, subdirectory, filename));
+            if options.dry_run {
+                println!("Would install {}", destination);
+            } else {
+                create_parent(&destination, library.mode)?;
+                install_path(p, &destination, library.mode)?;
+            }
+
+            // add entry to fp-lib-table
+            let nickname = library.nickname(&file_stem(p));
+            let uri = table_uri(p, &destination, library.mode);
+            let row = LibTableRow::new(&nickname, "KiCad", &uri, &descr);
+            if add_row(&mut fp_lib_table, row.clone(), "fp-lib-table") {
+                if options.dry_run {
+                    report_row(
+                        "fp-lib-table",
+                        &row,
+                        replaced.as_ref().map(|x| &x.fp_lib_rows),
+                    );
+                }
+                library.fp_lib_rows.push(row);
+            }
+        }
 
-            // TODO: Add entry to fp-lib-table
-            /*
-            let mut fp_lib_table_file = fs::OpenOptions::new()
-                .write(true)
-                .append(true)
-                .open(config.fp_lib_table.clone())?;
+        if options.dry_run {
+            // rows of the replaced library that are not added again
+            if let Some(replaced) = &replaced {
+                for (rows, new_rows, table_name) in [
+                    (
+                        &replaced.sym_lib_rows,
+                        &library.sym_lib_rows,
+                        "sym-lib-table",
+                    ),
+                    (&replaced.fp_lib_rows, &library.fp_lib_rows, "fp-lib-table"),
+                ]
+                .iter()
+                {
+                    for row in rows.iter() {
+                        if !new_rows.iter().any(|x| x.name == row.name) {
+                            println!("Would remove {} row: {}", table_name, row);
+                        }
+                    }
+                }
+            }
 
-            let _ = fp_lib_table_file.write(b"(test)")?;
-            */
+            if source_path == Path::new(&installation_path) {
+                remove_dir(&installation_path)?;
+            }
+            return Ok(());
+        }
+
+        println!("Updating sym-lib-table and fp-lib-table...");
+        sym_lib_table.save(&config.sym_lib_table)?;
+        fp_lib_table.save(&config.fp_lib_table)?;
+
+        // record checksums of the installed files, unless they change with their source
+        if !library.mode.tracks_source() {
+            library.manifest = checksum::manifest(
+                Path::new(&format!("{}/.kibrarian/libraries", env!("HOME"))),
+                &installed_directories(query),
+            )?;
+        }
+
+        // commits the library was installed at, for rollback
+        library.commits = options.commits.clone();
+        if let Some(commit) = git::head_commit(Path::new(&installation_path)) {
+            if library.commits.last() != Some(&commit) {
+                library.commits.push(commit);
+            }
         }
 
         println!("Adding installed library to installed.ron...");
         installed_libraries
             .lib_map
             .insert(library.name.clone(), library);
-        let serialized = ser::to_string(&installed_libraries)?;
-
-        let mut installed_file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))
-            .unwrap();
-
-        let _ = installed_file.write(serialized.as_bytes())?;
+        save_libraries(
+            &installed_libraries,
+            format!("{}/.config/kibrarian/installed.ron", env!("HOME")),
+        )?;
 
         Ok(())
     } else {
@@ -241,43 +1176,803 @@ pub fn install(config: Config, global: bool, query: &str) -> Result<(), Box<dyn
     }
 }
 
-pub fn uninstall(config: Config, global: bool, query: &str) -> Result<(), Box<dyn error::Error>> {
+pub fn verify(config: Config) -> Result<bool, Box<dyn error::Error>> {
+    let installed_libraries =
+        get_libraries(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))?;
+    let sym_lib_table = LibTable::load(&config.sym_lib_table, "sym_lib_table")?;
+    let fp_lib_table = LibTable::load(&config.fp_lib_table, "fp_lib_table")?;
+    let base = format!("{}/.kibrarian/libraries", env!("HOME"));
+
+    let mut names: Vec<&String> = installed_libraries.lib_map.keys().collect();
+    names.sort();
+
+    let mut clean = true;
+    for name in names {
+        let library = &installed_libraries.lib_map[name];
+        let mut problems = Vec::new();
+
+        if library.mode.tracks_source() {
+            println!(
+                "[{}]: installed with mode {}, file contents not checked",
+                name, library.mode
+            );
+        } else {
+            // compare installed files against the manifest
+            let current = checksum::manifest(Path::new(&base), &installed_directories(name))?;
+
+            let mut paths: Vec<&String> = library.manifest.keys().collect();
+            paths.sort();
+            for path in paths {
+                match current.get(path) {
+                    Some(sha256) if *sha256 == library.manifest[path] => {}
+                    Some(_) => problems.push(format!("modified: {}", path)),
+                    None => problems.push(format!("missing: {}", path)),
+                }
+            }
+
+            let mut paths: Vec<&String> = current.keys().collect();
+            paths.sort();
+            for path in paths {
+                if !library.manifest.contains_key(path) {
+                    problems.push(format!("unexpected: {}", path));
+                }
+            }
+        }
+
+        // compare lib-table rows
+        for (table, rows, table_name) in [
+            (&sym_lib_table, &library.sym_lib_rows, "sym-lib-table"),
+            (&fp_lib_table, &library.fp_lib_rows, "fp-lib-table"),
+        ]
+        .iter()
+        {
+            for row in rows.iter() {
+                match table.find(&row.name) {
+                    Some(x) if x == row => {}
+                    Some(_) => problems.push(format!("changed {} row: {}", table_name, row.name)),
+                    None => problems.push(format!("missing {} row: {}", table_name, row.name)),
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            println!("[{}]: ok", name);
+        } else {
+            clean = false;
+            println!("[{}]:", name);
+            for problem in problems.iter() {
+                println!("  {}", problem);
+            }
+        }
+    }
+
+    Ok(clean)
+}
+
+/// Remove the lib-table rows recorded for an installed library.
+pub fn remove_rows(config: &Config, library: &Library) -> Result<(), Box<dyn error::Error>> {
+    let mut sym_lib_table = LibTable::load(&config.sym_lib_table, "sym_lib_table")?;
+    for row in library.sym_lib_rows.iter() {
+        sym_lib_table.remove(&row.name);
+    }
+    sym_lib_table.save(&config.sym_lib_table)?;
+
+    let mut fp_lib_table = LibTable::load(&config.fp_lib_table, "fp_lib_table")?;
+    for row in library.fp_lib_rows.iter() {
+        fp_lib_table.remove(&row.name);
+    }
+    fp_lib_table.save(&config.fp_lib_table)?;
+
+    Ok(())
+}
+
+/// Remove a directory, or a symlink to one, if it exists.
+pub fn remove_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    match fs::symlink_metadata(&path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => fs::remove_file(path),
+        Ok(_) => fs::remove_dir_all(path),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Library directories of an installed library, relative to ~/.kibrarian/libraries.
+pub fn installed_directories(name: &str) -> Vec<String> {
+    vec![format!("symbols/{}", name), format!("footprints/{}", name)]
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// nicknames of the library files with one of the given extensions
+fn nicknames(library: &Library, files: &[(PathBuf, PathBuf)], extensions: &[&str]) -> Vec<String> {
+    files
+        .iter()
+        .map(|(p, _)| p)
+        .filter(|p| has_extension(p, extensions))
+        .filter(|p| library.selection.matches(&file_stem(p)))
+        .map(|p| library.nickname(&file_stem(p)))
+        .collect()
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    extensions
+        .iter()
+        .any(|x| path.extension() == Some(OsStr::new(x)))
+}
+
+// library files below the declared paths, each with the directory it was found in relative
+// to the matching path, matched directories are searched recursively
+fn discover(
+    root: &Path,
+    paths: &[&str],
+    extensions: &[&str],
+) -> Result<Vec<(PathBuf, PathBuf)>, Box<dyn error::Error>> {
+    let mut files = Vec::new();
+    for path in paths.iter() {
+        let pattern = format!(
+            "{}/{}",
+            Pattern::escape(&root.to_string_lossy()),
+            path.trim_end_matches('/')
+        );
+
+        let mut found = false;
+        for matched in glob::glob(&pattern)? {
+            let matched = matched?;
+            found = true;
+            if has_extension(&matched, extensions) {
+                files.push((matched, PathBuf::new()));
+            } else if matched.is_dir() {
+                discover_dir(&matched, &matched, extensions, &mut files)?;
+            }
+        }
+        if !found {
+            println!("Nothing found at {}.", path);
+        }
+    }
+
+    // a file matched by more than one path is installed once
+    let mut seen = Vec::new();
+    files.retain(|(p, _)| {
+        if seen.contains(p) {
+            false
+        } else {
+            seen.push(p.clone());
+            true
+        }
+    });
+
+    Ok(files)
+}
+
+fn discover_dir(
+    base: &Path,
+    directory: &Path,
+    extensions: &[&str],
+    files: &mut Vec<(PathBuf, PathBuf)>,
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(directory)?
+        .map(|res| res.map(|e| e.path()))
+        .collect::<Result<Vec<_>, io::Error>>()?;
+    entries.sort();
+
+    for entry in entries {
+        if has_extension(&entry, extensions) {
+            let subdirectory = directory.strip_prefix(base).unwrap_or(directory);
+            files.push((entry, subdirectory.to_path_buf()));
+        } else if entry.is_dir() {
+            discover_dir(base, &entry, extensions, files)?;
+        }
+    }
+
+    Ok(())
+}
+
+// path of an installed library file inside the library directory
+fn layout_path(layout: Layout, subdirectory: &Path, filename: &str) -> String {
+    match layout {
+        Layout::Preserve => subdirectory.join(filename).to_string_lossy().into_owned(),
+        Layout::Flatten => filename.to_owned(),
+    }
+}
+
+// create the directory of an installed library file, direct installs create nothing
+fn create_parent(destination: &str, mode: InstallMode) -> io::Result<()> {
+    match Path::new(destination).parent() {
+        Some(parent) if mode != InstallMode::Direct => fs::create_dir_all(parent),
+        _ => Ok(()),
+    }
+}
+
+// describe each nickname already taken in a lib table, or used twice by the library
+fn nickname_conflicts(
+    table: &LibTable,
+    nicknames: &[String],
+    installed_libraries: &Libraries,
+) -> Vec<String> {
+    let mut conflicts = Vec::new();
+    for (i, nickname) in nicknames.iter().enumerate() {
+        if nicknames[..i].contains(nickname) {
+            conflicts.push(format!(
+                "{} is used by more than one library file",
+                nickname
+            ));
+        } else if let Some(row) = table.find(nickname) {
+            let owner = installed_libraries.lib_map.values().find(|library| {
+                library.sym_lib_rows.contains(row) || library.fp_lib_rows.contains(row)
+            });
+            match owner {
+                Some(library) => conflicts.push(format!(
+                    "{} is already used by kibrarian library {}",
+                    nickname, library.name
+                )),
+                None => conflicts.push(format!(
+                    "{} is already used by a library not managed by kibrarian ({})",
+                    nickname, row.uri
+                )),
+            }
+        }
+    }
+
+    conflicts
+}
+
+// report a row a dry run would add, compared to the row of the library it replaces
+fn report_row(table_name: &str, row: &LibTableRow, replaced_rows: Option<&Vec<LibTableRow>>) {
+    match replaced_rows.and_then(|rows| rows.iter().find(|x| x.name == row.name)) {
+        Some(replaced) if replaced == row => {}
+        Some(_) => println!("Would change {} row: {}", table_name, row),
+        None => println!("Would add {} row: {}", table_name, row),
+    }
+}
+
+// add a row to a lib table, returns false if the nickname is already taken
+fn add_row(table: &mut LibTable, row: LibTableRow, table_name: &str) -> bool {
+    let name = row.name.clone();
+    if table.add(row) {
+        true
+    } else {
+        println!(
+            "{} already has a library named {}, not adding it.",
+            table_name, name
+        );
+        false
+    }
+}
+
+// copy the declared paths of a local library into extra, or link the whole directory
+fn copy_local(
+    library: &Library,
+    local_path: &Path,
+    installation_path: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    if !local_path.is_dir() {
+        return Err(Box::new(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a directory.", local_path.display()),
+        )));
+    }
+
+    if library.link {
+        println!("linking {} to {}", installation_path, local_path.display());
+        return Ok(symlink(&fs::canonicalize(local_path)?, installation_path)?);
+    }
+
+    println!("copying from: {}", local_path.display());
+    let mut sources = Vec::new();
+    for path in library.paths() {
+        let pattern = format!(
+            "{}/{}",
+            Pattern::escape(&local_path.to_string_lossy()),
+            path.trim_end_matches('/')
+        );
+        for matched in glob::glob(&pattern)? {
+            sources.push(matched?);
+        }
+    }
+    sources.sort();
+    sources.dedup();
+
+    for source in sources {
+        let relative = source.strip_prefix(local_path).unwrap_or(&source);
+        let destination = Path::new(installation_path).join(relative);
+        if destination.exists() {
+            continue;
+        }
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if source.is_dir() {
+            let mut options = dir::CopyOptions::new();
+            options.copy_inside = true;
+            dir::copy(source, destination, &options)?;
+        } else {
+            fs::copy(source, destination)?;
+        }
+    }
+
+    Ok(())
+}
+
+// install a library file or directory from the source according to the install mode
+fn install_path(
+    source: &Path,
+    destination: &str,
+    mode: InstallMode,
+) -> Result<(), Box<dyn error::Error>> {
+    match mode {
+        InstallMode::Copy if source.is_dir() => {
+            let mut options = dir::CopyOptions::new();
+            options.copy_inside = true;
+            dir::copy(source, destination, &options)?;
+        }
+        InstallMode::Copy => {
+            fs::copy(source, destination)?;
+        }
+        InstallMode::Symlink => symlink(source, destination)?,
+        InstallMode::Hardlink => hard_link_all(source, Path::new(destination))?,
+        InstallMode::Direct => {}
+    }
+
+    Ok(())
+}
+
+// lib-table uri of an installed library file, the source itself for direct installs
+fn table_uri(source: &Path, destination: &str, mode: InstallMode) -> String {
+    match mode {
+        InstallMode::Direct => source.to_string_lossy().into_owned(),
+        _ => destination.to_owned(),
+    }
+}
+
+// hard link a file, or every file below a directory
+fn hard_link_all(source: &Path, destination: &Path) -> io::Result<()> {
+    if !source.is_dir() {
+        return fs::hard_link(source, destination);
+    }
+
+    fs::create_dir_all(destination)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        hard_link_all(&entry.path(), &destination.join(entry.file_name()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Result<()> {
+    if original.as_ref().is_dir() {
+        std::os::windows::fs::symlink_dir(original, link)
+    } else {
+        std::os::windows::fs::symlink_file(original, link)
+    }
+}
+
+pub fn uninstall(
+    config: Config,
+    _global: bool,
+    query: &str,
+    options: &UninstallOptions,
+) -> Result<(), Box<dyn error::Error>> {
     // check if query is in installed.ron
-    if let Some(library) = search(config.libraries, query) {
+    if let Some(library) = search(config.libraries.clone(), query) {
         let mut installed_libraries =
             get_libraries(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))?;
+        for name in options.removed.iter() {
+            installed_libraries.lib_map.remove(name);
+        }
 
         if !installed_libraries.lib_map.contains_key(&library.name[..]) {
             return Err(Box::new(LibraryError::LibraryNotInstalledError));
         }
 
-        // remove directories in .kibrarian/extra and .kibrarian/libaries
-        fs::remove_dir_all(format!("{}/.kibrarian/extra/{}", env!("HOME"), query))?;
-        fs::remove_dir_all(format!(
-            "{}/.kibrarian/libraries/symbols/{}",
-            env!("HOME"),
-            query
-        ))?;
-        fs::remove_dir_all(format!(
-            "{}/.kibrarian/libraries/footprints/{}",
-            env!("HOME"),
-            query
-        ))?;
-
-        // remove installed library from installed map and write to installed.ron
-        installed_libraries.lib_map.remove(query).unwrap();
-        let serialized = ser::to_string(&installed_libraries)?;
-        let mut installed_file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))
-            .unwrap();
-
-        let _ = installed_file.write(serialized.as_bytes())?;
+        // keep libraries other installed libraries depend on
+        let required_by = dependencies::dependents(&installed_libraries, &library.name);
+        if !required_by.is_empty() && !options.force {
+            return Err(Box::new(LibraryError::LibraryRequiredError(required_by)));
+        }
+
+        // keep libraries providing symbols and footprints projects use
+        if !options.force && !config.projects.is_empty() {
+            let installed = &installed_libraries.lib_map[query];
+            let impact = Impact::analyze(
+                &config,
+                &Contents::installed(installed),
+                &Contents::default(),
+            )?;
+            impact.print("Uninstalling", query);
+            if impact.is_blocking() {
+                return Err(Box::new(LibraryError::LibraryImpactError(vec![
+                    query.to_owned()
+                ])));
+            }
+        }
+
+        if options.dry_run {
+            let installed = &installed_libraries.lib_map[query];
+            if cache::keeps(installed) {
+                println!(
+                    "Would move {} to {}",
+                    cache::installation_path(query).display(),
+                    cache::cached_path(query).display()
+                );
+            } else {
+                println!("Would remove {}", cache::installation_path(query).display());
+            }
+            for directory in installed_directories(query) {
+                let path = format!("{}/.kibrarian/libraries/{}", env!("HOME"), directory);
+                for file in checksum::walk(&path)? {
+                    println!("Would remove {}", file.display());
+                }
+            }
+            for row in installed.sym_lib_rows.iter() {
+                println!("Would remove sym-lib-table row: {}", row);
+            }
+            for row in installed.fp_lib_rows.iter() {
+                println!("Would remove fp-lib-table row: {}", row);
+            }
+            return Ok(());
+        }
+
+        // keep clones in the cache for a later install, remove directories in
+        // .kibrarian/extra and .kibrarian/libaries, tolerating directories that were already
+        // removed by hand
+        cache::keep(&installed_libraries.lib_map[query])?;
+        remove_dir(format!("{}/.kibrarian/extra/{}", env!("HOME"), query))?;
+        for directory in installed_directories(query) {
+            remove_dir(format!(
+                "{}/.kibrarian/libraries/{}",
+                env!("HOME"),
+                directory
+            ))?;
+        }
+
+        // remove installed library from installed map and its lib-table rows
+        let installed = installed_libraries.lib_map.remove(query).unwrap();
+        remove_rows(&config, &installed)?;
+
+        // write to installed.ron
+        save_libraries(
+            &installed_libraries,
+            format!("{}/.config/kibrarian/installed.ron", env!("HOME")),
+        )?;
 
         Ok(())
     } else {
         Err(Box::new(LibraryError::LibraryNotFoundError))
     }
 }
+
+// commits an installed library was installed at, ending with the commit its clone is at,
+// which installations from before the history was recorded lack
+fn history(installed: &Library, commit: Option<&String>) -> Vec<String> {
+    let mut commits = installed.commits.clone();
+    if let Some(commit) = commit {
+        if commits.last() != Some(commit) {
+            commits.push(commit.clone());
+        }
+    }
+    commits
+}
+
+// options installing a library again the way it is installed, continuing its history
+fn reinstall_options(installed: &Library, commit: Option<&String>) -> InstallOptions {
+    let mut options = InstallOptions::new();
+    options.mode(installed.mode);
+    options.selection(installed.selection.clone());
+    options.dependency(installed.dependency);
+    options.commits(history(installed, commit));
+    if let Some(version_requirement) = &installed.version_requirement {
+        options.version(version_requirement);
+    }
+    options
+}
+
+pub fn update(
+    config: Config,
+    global: bool,
+    query: Option<&str>,
+    dry_run: bool,
+    force: bool,
+) -> Result<bool, Box<dyn error::Error>> {
+    // reinstalling uninstalls first, project installations would never be put back
+    if !global {
+        return Err(Box::new(LibraryError::LibraryScopeError));
+    }
+
+    // update the library index if it is a git repository
+    if let Some(sources) = Path::new(&config.libraries).parent() {
+        if config.offline {
+            println!("Offline, using the library index in {}", sources.display());
+        } else if sources.join(".git").exists() && dry_run {
+            println!("Would pull library index in {}", sources.display());
+        } else if sources.join(".git").exists() {
+            git::pull(&sources.to_string_lossy(), |url| config.rewrite_url(url))?;
+        }
+    }
+
+    let installed_libraries =
+        get_libraries(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))?;
+
+    let mut names: Vec<&String> = match query {
+        Some(query) => match installed_libraries.lib_map.get_key_value(query) {
+            Some((name, _)) => vec![name],
+            None => return Err(Box::new(LibraryError::LibraryNotInstalledError)),
+        },
+        None => installed_libraries.lib_map.keys().collect(),
+    };
+    names.sort();
+
+    // keep libraries that can't be reinstalled offline
+    if config.offline {
+        names.retain(|name| {
+            let cached = cache::contains(&installed_libraries.lib_map[*name]);
+            if !cached {
+                println!("{} is not in the cache, not updating it offline.", name);
+            }
+            cached
+        });
+    }
+
+    // read the installed files and commits before their clones move to the cache when they
+    // are reinstalled, libraries in symlink and direct mode point into them
+    let mut old_contents: HashMap<&str, Contents> = HashMap::new();
+    let mut old_commits: HashMap<&str, String> = HashMap::new();
+    for name in names.iter() {
+        if !config.projects.is_empty() {
+            old_contents.insert(
+                name,
+                Contents::installed(&installed_libraries.lib_map[*name]),
+            );
+        }
+        let installation = format!("{}/.kibrarian/extra/{}", env!("HOME"), name);
+        if let Some(commit) = git::head_commit(Path::new(&installation)) {
+            old_commits.insert(name, commit);
+        }
+    }
+
+    // clone the new revisions of all libraries concurrently, resolution errors are
+    // reported when each library is reinstalled
+    let _staging = Staging::begin()?;
+    let index = get_libraries(config.libraries.clone())?;
+    if !dry_run {
+        let mut planned: Vec<Library> = Vec::new();
+        for name in names.iter() {
+            let mut others = Libraries::new();
+            others.lib_map = installed_libraries.lib_map.clone();
+            others.lib_map.remove(*name);
+            let options = reinstall_options(&installed_libraries.lib_map[*name], None);
+            if let Ok(plan) = plan(&index, &others, name, &options, &config) {
+                for library in plan {
+                    if !planned.iter().any(|x| x.name == library.name) {
+                        planned.push(library);
+                    }
+                }
+            }
+        }
+        staging::stage(&config, &planned);
+    }
+
+    // reinstall each library from its current index entry, a failure doesn't stop the others
+    let mut summary = Summary::new(if dry_run { "Would update" } else { "Updated" });
+    for name in names {
+        println!("Updating {}...", name);
+        let installed = &installed_libraries.lib_map[name];
+        let mut options = reinstall_options(installed, old_commits.get(&name[..]));
+
+        // compare the new files with the installed ones against the projects in config.ron
+        if let Some(old) = old_contents.get(&name[..]) {
+            let mut others = Libraries::new();
+            others.lib_map = installed_libraries.lib_map.clone();
+            others.lib_map.remove(name);
+            let impact = plan(&index, &others, name, &options, &config).and_then(|plan| {
+                let library = plan
+                    .into_iter()
+                    .find(|x| &x.name == name)
+                    .unwrap_or_else(|| index.lib_map[name].clone());
+                Impact::of_update(&config, old, installed, &library, dry_run)
+            });
+
+            match impact {
+                Ok(impact) => {
+                    impact.print("Updating", name);
+                    if impact.is_blocking() && !force {
+                        println!("Not updating {}.", name);
+                        let blocked = LibraryError::LibraryImpactError(vec![name.clone()]);
+                        summary.record(name, Err(Box::new(blocked)));
+                        continue;
+                    }
+                }
+                Err(e) => {
+                    summary.record(name, Err(e));
+                    continue;
+                }
+            }
+        }
+
+        // a dry run previews the reinstall against the current installation
+        if dry_run {
+            options.dry_run(true);
+            options.replace(true);
+            summary.record(name, install(config.clone(), global, name, &options));
+            continue;
+        }
+
+        summary.record(name, reinstall(&config, global, installed, &options));
+    }
+
+    Ok(summary.print())
+}
+
+// uninstall an installed library and install it again with options, an install that fails
+// puts the library back at the commit it was installed at, from its kept clone if it has one
+fn reinstall(
+    config: &Config,
+    global: bool,
+    installed: &Library,
+    options: &InstallOptions,
+) -> Result<(), Box<dyn error::Error>> {
+    let name = &installed.name;
+    let installation = cache::installation_path(name);
+    let commit = git::head_commit(&installation);
+
+    let mut uninstall_options = UninstallOptions::new();
+    uninstall_options.force(true);
+    uninstall(config.clone(), global, name, &uninstall_options)?;
+    let error = match install(config.clone(), global, name, options) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+
+    // a failed install may leave its clone behind, keep it for the reinstall
+    println!("Reinstalling {} as it was...", name);
+    if !cache::keep(installed)? {
+        remove_dir(&installation)?;
+    }
+    let mut previous = reinstall_options(installed, None);
+    if let Some(revision) = commit.or_else(|| installed.revision.clone()) {
+        previous.revision(revision);
+    }
+    let mut config = config.clone();
+    config.offline = config.offline || cache::contains(installed);
+    if let Err(e) = install(config, global, name, &previous) {
+        println!("{} could not be reinstalled: {}", name, e);
+    }
+    Err(error)
+}
+
+/// Reinstall a library at the commit it was installed at before the current one, or at the
+/// revision to, from its clone. Commits rolled back from leave the history of the library,
+/// so rolling back again goes further back.
+pub fn rollback(
+    config: Config,
+    global: bool,
+    query: &str,
+    to: Option<&str>,
+    dry_run: bool,
+) -> Result<(), Box<dyn error::Error>> {
+    // rolling back reinstalls, project installations would never be put back
+    if !global {
+        return Err(Box::new(LibraryError::LibraryScopeError));
+    }
+
+    let installed_libraries =
+        get_libraries(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))?;
+    let installed = match installed_libraries.lib_map.get(query) {
+        Some(x) => x,
+        None => return Err(Box::new(LibraryError::LibraryNotInstalledError)),
+    };
+    let clone_path = match cache::clone_path(query) {
+        Some(x) => x,
+        None => return Err(Box::new(LibraryError::LibraryHistoryError)),
+    };
+
+    let current = git::head_commit(&clone_path);
+    let mut commits = history(installed, current.as_ref());
+    let target = match to {
+        Some(revision) => revision.to_owned(),
+        None => match commits.iter().rev().find(|x| Some(*x) != current.as_ref()) {
+            Some(commit) => commit.clone(),
+            None => return Err(Box::new(LibraryError::LibraryHistoryError)),
+        },
+    };
+
+    // a revision that is not in the clone yet is fetched by the installation
+    let target = match git::commit_id(&clone_path, &target) {
+        Ok(commit) => {
+            if let Some(index) = commits.iter().rposition(|x| *x == commit) {
+                commits.truncate(index);
+            }
+            commit
+        }
+        Err(_) => target,
+    };
+
+    println!(
+        "Rolling back {} from {} to {}...",
+        query,
+        current.as_deref().unwrap_or("an unknown commit"),
+        target
+    );
+    let mut options = reinstall_options(installed, None);
+    options.revision(target);
+    options.commits(commits);
+
+    // a dry run previews the reinstall against the current installation
+    if dry_run {
+        options.dry_run(true);
+        options.replace(true);
+        return install(config, global, query, &options);
+    }
+
+    reinstall(&config, global, installed, &options)
+}
+
+pub fn list() -> Result<(), Box<dyn error::Error>> {
+    let installed_libraries =
+        get_libraries(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))?;
+
+    let mut libraries: Vec<&Library> = installed_libraries.lib_map.values().collect();
+    libraries.sort_by(|a, b| a.name.cmp(&b.name));
+    if libraries.is_empty() {
+        println!("No libraries installed.");
+    }
+
+    for library in libraries {
+        println!("{}", library);
+        let names = |rows: &Vec<LibTableRow>| {
+            rows.iter()
+                .map(|x| &x.name[..])
+                .collect::<Vec<&str>>()
+                .join(", ")
+        };
+        println!("\tsymbols: {}", names(&library.sym_lib_rows));
+        println!("\tfootprints: {}", names(&library.fp_lib_rows));
+    }
+
+    Ok(())
+}
+
+pub fn info(config: Config, query: &str) -> Result<(), Box<dyn error::Error>> {
+    let library = match search(config.libraries.clone(), query) {
+        Some(x) => x,
+        None => return Err(Box::new(LibraryError::LibraryNotFoundError)),
+    };
+    let installed_libraries =
+        get_libraries(format!("{}/.config/kibrarian/installed.ron", env!("HOME")))?;
+
+    match installed_libraries.lib_map.get(query) {
+        Some(installed) => match &installed.revision {
+            Some(revision) => println!("installed: {}", revision),
+            None => println!("installed"),
+        },
+        None => println!("not installed"),
+    }
+
+    let required_by = dependencies::dependents(&installed_libraries, query);
+    if !required_by.is_empty() {
+        println!("required by: {}", required_by.join(", "));
+    }
+
+    let versions = library.versions(&config)?;
+    if versions.is_empty() {
+        println!("no versions available");
+    } else {
+        println!("versions:");
+        for (version, revision) in versions.iter() {
+            println!("  {} ({})", version, revision);
+        }
+    }
+
+    Ok(())
+}
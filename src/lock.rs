@@ -0,0 +1,90 @@
+use fs2::FileExt;
+use std::io::Write;
+use std::path::PathBuf;
+use std::{error, fmt, fs, process};
+
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+pub enum LockError {
+    LockedError(Option<u32>),
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LockError::LockedError(Some(pid)) => write!(
+                f,
+                "Another kibrarian process (PID {}) is modifying libraries, use --wait to wait for it.",
+                pid
+            ),
+            LockError::LockedError(None) => write!(
+                f,
+                "Another kibrarian process is modifying libraries, use --wait to wait for it."
+            ),
+        }
+    }
+}
+
+impl error::Error for LockError {
+    fn description(&self) -> &str {
+        match self {
+            LockError::LockedError(_) => "Another kibrarian process is modifying libraries.",
+        }
+    }
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+/// Advisory lock held while installed.ron, the lib tables or ~/.kibrarian are modified,
+/// released when dropped. The operating system releases it when its process dies, so the lock
+/// file is never removed and a crashed process leaves no stale lock behind.
+pub struct Lock {
+    file: fs::File,
+}
+
+impl Lock {
+    pub fn acquire(wait: bool) -> Result<Lock, Box<dyn error::Error>> {
+        let path = PathBuf::from(format!("{}/.kibrarian/kibrarian.lock", env!("HOME")));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        match file.try_lock_exclusive() {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == fs2::lock_contended_error().kind() => {
+                // the holder writes its PID once it has the lock
+                let pid = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|x| x.trim().parse::<u32>().ok());
+                if !wait {
+                    return Err(Box::new(LockError::LockedError(pid)));
+                }
+                match pid {
+                    Some(pid) => println!("Waiting for kibrarian process {}...", pid),
+                    None => println!("Waiting for another kibrarian process..."),
+                }
+                file.lock_exclusive()?;
+            }
+            Err(e) => return Err(Box::new(e)),
+        }
+
+        let mut lock = Lock { file };
+        lock.file.set_len(0)?;
+        write!(lock.file, "{}", process::id())?;
+        Ok(lock)
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
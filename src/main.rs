@@ -1,7 +1,67 @@
-use clap::{App, AppSettings, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches};
+mod adopt;
+mod archive;
+mod batch;
+mod cache;
+mod checksum;
 mod config;
+mod dependencies;
+mod diff;
+mod doctor;
+mod export;
 mod git;
+mod history;
+mod impact;
+mod lib_table;
 mod libraries;
+mod lock;
+mod mirror;
+mod progress;
+mod scan;
+mod sexpr;
+mod staging;
+
+// wait for other kibrarian processes to release the lock instead of failing
+fn wait_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("wait")
+        .help("Wait for other kibrarian processes to finish.")
+        .long("wait")
+}
+
+// report changes without making them
+fn dry_run_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("dry-run")
+        .help("Show what would change without changing anything.")
+        .long("dry-run")
+}
+
+// targets given as arguments followed by those listed in --from-file
+fn targets(matches: &ArgMatches) -> std::io::Result<Vec<String>> {
+    let mut targets: Vec<String> = match matches.values_of("target") {
+        Some(values) => values.map(|x| x.to_owned()).collect(),
+        None => Vec::new(),
+    };
+    if let Some(path) = matches.value_of("from-file") {
+        targets.append(&mut batch::read_targets(path)?);
+    }
+    Ok(targets)
+}
+
+// limit the number of concurrent clones
+fn jobs_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("jobs")
+        .help("Number of libraries to clone at the same time.")
+        .short("j")
+        .long("jobs")
+        .takes_value(true)
+}
+
+// work from the cache without network access
+fn offline_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("offline")
+        .help("Resolve and install libraries from the cache only.")
+        .long("offline")
+}
 
 fn main() {
     // create the App with clap
@@ -13,33 +73,84 @@ fn main() {
         .subcommand(
             App::new("install")
                 .about("Installs a library.")
+                .arg(wait_arg())
+                .arg(dry_run_arg())
+                .arg(jobs_arg())
+                .arg(offline_arg())
+                .arg(
+                    Arg::with_name("mode")
+                        .help("Copy, symlink or hardlink installed files, or point lib tables directly at the source.")
+                        .long("mode")
+                        .takes_value(true)
+                        .possible_values(&["copy", "symlink", "hardlink", "direct"]),
+                )
+                .arg(
+                    Arg::with_name("only")
+                        .help("Comma separated symbol and footprint libraries to install.")
+                        .long("only")
+                        .takes_value(true)
+                        .use_delimiter(true),
+                )
                 .arg(
                     Arg::with_name("global")
                         .help("Indicate global.")
                         .short("g")
                         .long("global"),
                 )
+                .arg(
+                    Arg::with_name("from-file")
+                        .help("File listing libraries to install, one per line.")
+                        .long("from-file")
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("target")
-                        .help("Target library to install.")
+                        .help("Target libraries to install, optionally with a version requirement as in foo@^2.1.")
                         .index(1)
-                        .required(true),
+                        .multiple(true)
+                        .required_unless("from-file"),
                 ),
         )
         .subcommand(
             App::new("uninstall")
                 .about("Uninstalls a library.")
+                .arg(wait_arg())
+                .arg(dry_run_arg())
+                .arg(
+                    Arg::with_name("force")
+                        .help("Uninstall even if other libraries depend on it or projects use it.")
+                        .long("force"),
+                )
                 .arg(
                     Arg::with_name("global")
                         .help("Indicate global.")
                         .short("g")
                         .long("global"),
                 )
+                .arg(
+                    Arg::with_name("from-file")
+                        .help("File listing libraries to uninstall, one per line.")
+                        .long("from-file")
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("target")
-                        .help("Target library to uninstall.")
+                        .help("Target libraries to uninstall.")
                         .index(1)
-                        .required(true),
+                        .multiple(true)
+                        .required_unless("from-file"),
+                ),
+        )
+        .subcommand(
+            App::new("autoremove")
+                .about("Uninstall dependencies no installed library needs anymore.")
+                .arg(wait_arg())
+                .arg(dry_run_arg())
+                .arg(
+                    Arg::with_name("global")
+                        .help("Indicate global.")
+                        .short("g")
+                        .long("global"),
                 ),
         )
         .subcommand(
@@ -50,41 +161,373 @@ fn main() {
                     .required(true),
             ),
         )
-        .subcommand(App::new("update").about("Update libraries."))
-        .subcommand(App::new("setup").about("Setup kibrarian configuration."))
+        .subcommand(
+            App::new("info")
+                .about("Show a library with its installed and available versions.")
+                .arg(offline_arg())
+                .arg(
+                    Arg::with_name("target")
+                        .help("Library to show.")
+                        .index(1)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            App::new("update")
+                .about("Update libraries.")
+                .arg(wait_arg())
+                .arg(dry_run_arg())
+                .arg(jobs_arg())
+                .arg(offline_arg())
+                .arg(
+                    Arg::with_name("global")
+                        .help("Indicate global.")
+                        .short("g")
+                        .long("global"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .help("Update libraries even if projects use symbols or footprints they remove.")
+                        .long("force"),
+                )
+                .arg(
+                    Arg::with_name("target")
+                        .help("Target library to update, all installed libraries if omitted.")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            App::new("rollback")
+                .about("Reinstall a library at the commit it was installed at before.")
+                .arg(wait_arg())
+                .arg(dry_run_arg())
+                .arg(offline_arg())
+                .arg(
+                    Arg::with_name("global")
+                        .help("Indicate global.")
+                        .short("g")
+                        .long("global"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .help("Commit or revision to roll back to instead of the previous one.")
+                        .long("to")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("target")
+                        .help("Target library to roll back.")
+                        .index(1)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            App::new("adopt")
+                .about("Adopt unmanaged libraries found in the KiCad lib tables.")
+                .arg(wait_arg())
+                .arg(dry_run_arg()),
+        )
+        .subcommand(App::new("verify").about("Verify installed library files and lib-table rows."))
+        .subcommand(
+            App::new("doctor")
+                .about("Diagnose inconsistencies between installed libraries and lib tables.")
+                .arg(wait_arg())
+                .arg(
+                    Arg::with_name("fix")
+                        .help("Repair or forget broken and orphaned entries.")
+                        .long("fix"),
+                ),
+        )
+        .subcommand(
+            App::new("cache")
+                .about("Manage clones of libraries and downloaded archives.")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    App::new("du").about("Show the disk space used by each library."),
+                )
+                .subcommand(
+                    App::new("clean")
+                        .about("Remove clones and archives of libraries that are not installed.")
+                        .arg(wait_arg())
+                        .arg(dry_run_arg()),
+                )
+                .subcommand(
+                    App::new("gc")
+                        .about("Run git gc on the clones of libraries.")
+                        .arg(wait_arg()),
+                ),
+        )
+        .subcommand(
+            App::new("export")
+                .about("Write the installed libraries to a file for import on another machine.")
+                .arg(
+                    Arg::with_name("file")
+                        .help("File to write.")
+                        .index(1)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            App::new("import")
+                .about("Install the libraries of an exported file.")
+                .arg(wait_arg())
+                .arg(dry_run_arg())
+                .arg(
+                    Arg::with_name("file")
+                        .help("File written by export.")
+                        .index(1)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            App::new("scan")
+                .about("Find the libraries a KiCad project references and install the missing ones.")
+                .arg(wait_arg())
+                .arg(dry_run_arg())
+                .arg(jobs_arg())
+                .arg(offline_arg())
+                .arg(
+                    Arg::with_name("install")
+                        .help("Install the libraries providing missing nicknames.")
+                        .long("install"),
+                )
+                .arg(
+                    Arg::with_name("global")
+                        .help("Indicate global.")
+                        .short("g")
+                        .long("global"),
+                )
+                .arg(
+                    Arg::with_name("project")
+                        .help("Project directory, project file, schematic or board.")
+                        .index(1)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            App::new("diff")
+                .about("Compare the symbols and footprints of two revisions of a library.")
+                .arg(wait_arg())
+                .arg(offline_arg())
+                .arg(
+                    Arg::with_name("library")
+                        .help("Library to compare.")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .help("Revision to compare from, the installed commit by default.")
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .help("Revision to compare to, the revision in the library index or the default branch by default.")
+                        .index(3),
+                ),
+        )
+        .subcommand(
+            App::new("mirror")
+                .about("Mirror the library index and its git libraries to a directory.")
+                .arg(
+                    Arg::with_name("directory")
+                        .help("Directory holding the bare mirrors.")
+                        .index(1)
+                        .required(true),
+                ),
+        )
+        .subcommand(App::new("list").about("List installed libraries."))
+        .subcommand(App::new("history").about("List operations that can be undone."))
+        .subcommand(
+            App::new("undo")
+                .about("Restore installed.ron and the lib tables to before the last operations.")
+                .arg(wait_arg())
+                .arg(dry_run_arg())
+                .arg(
+                    Arg::with_name("count")
+                        .help("Number of operations to undo.")
+                        .index(1)
+                        .default_value("1"),
+                ),
+        )
+        .subcommand(
+            App::new("setup")
+                .about("Setup kibrarian configuration.")
+                .arg(wait_arg()),
+        )
         .get_matches();
 
+    // cache takes its options on its own subcommands
+    let sub_matches = match matches.subcommand() {
+        ("cache", Some(cache_matches)) => cache_matches.subcommand().1,
+        (_, sub_matches) => sub_matches,
+    };
+    let dry_run = sub_matches.is_some_and(|x| x.is_present("dry-run"));
+
+    // hold the lock for commands modifying libraries or configuration, dry runs too as they
+    // share the staging directory
+    let locking = match matches.subcommand() {
+        ("install", _) | ("uninstall", _) | ("update", _) | ("adopt", _) => true,
+        ("rollback", _) => true,
+        ("autoremove", _) | ("import", _) | ("diff", _) => true,
+        ("setup", _) | ("undo", _) => true,
+        ("doctor", Some(doctor_matches)) => doctor_matches.is_present("fix"),
+        ("scan", Some(scan_matches)) => scan_matches.is_present("install"),
+        ("cache", Some(cache_matches)) => cache_matches.subcommand_name() != Some("du"),
+        _ => false,
+    };
+    let lock = if locking {
+        let wait = sub_matches.is_some_and(|x| x.is_present("wait"));
+        match lock::Lock::acquire(wait) {
+            Ok(x) => Some(x),
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
     // create config.ron path
     let config_path = format!("{}/.config/kibrarian/config.ron", env!("HOME"));
 
-    if let Some(config_file) = config::load(&config_path[..]) {
+    let mut success = true;
+    if let Some(mut config_file) = config::load(&config_path[..]) {
+        if let (_, Some(sub_matches)) = matches.subcommand() {
+            if sub_matches.is_present("offline") {
+                config_file.offline(true);
+            }
+            if let Some(jobs) = sub_matches.value_of("jobs") {
+                match jobs.parse() {
+                    Ok(jobs) => config_file.jobs(jobs),
+                    Err(e) => {
+                        println!("Invalid number of jobs: {}", e);
+                        drop(lock);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+
+        // back up installed.ron and the lib tables before mutating commands, the backup is
+        // dropped again if the command changes neither
+        let mutating = match matches.subcommand() {
+            ("setup", _) | ("undo", _) | ("cache", _) | ("diff", _) => false,
+            _ => locking && !dry_run,
+        };
+        let snapshot = if mutating {
+            let operation = std::env::args().skip(1).collect::<Vec<String>>().join(" ");
+            match history::snapshot(&config_file, &operation) {
+                Ok(x) => Some(x),
+                Err(e) => {
+                    println!("Couldn't back up installed.ron and lib tables: {}", e);
+                    drop(lock);
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            None
+        };
+
         // handle subcommands and args
         match matches.subcommand() {
             ("install", Some(install_matches)) => {
-                println!(
-                    "Installing {}...",
-                    install_matches.value_of("target").unwrap()
-                );
+                let targets = match targets(install_matches) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        println!("{}", e);
+                        drop(snapshot);
+                        drop(lock);
+                        std::process::exit(1);
+                    }
+                };
 
-                match libraries::install(
-                    config_file,
-                    install_matches.is_present("global"),
-                    install_matches.value_of("target").unwrap(),
-                ) {
-                    Ok(()) => {}
-                    Err(e) => println!("{}", e),
+                let mut options = libraries::InstallOptions::new();
+                if let Some(mode) = install_matches.value_of("mode") {
+                    options.mode(mode.parse().unwrap());
+                }
+                if let Some(only) = install_matches.values_of("only") {
+                    options.only(&only.collect::<Vec<&str>>());
+                }
+                options.dry_run(dry_run);
+
+                if targets.len() == 1 {
+                    let mut target = &targets[0][..];
+                    println!("Installing {}...", target);
+                    if let Some(index) = target.find('@') {
+                        options.version(&target[index + 1..]);
+                        target = &target[..index];
+                    }
+
+                    match libraries::install(
+                        config_file,
+                        install_matches.is_present("global"),
+                        target,
+                        &options,
+                    ) {
+                        Ok(()) => {}
+                        Err(e) => println!("{}", e),
+                    }
+                } else {
+                    match batch::install_all(
+                        config_file,
+                        install_matches.is_present("global"),
+                        targets,
+                        &options,
+                    ) {
+                        Ok(true) => {}
+                        Ok(false) => success = false,
+                        Err(e) => println!("{}", e),
+                    }
                 }
             }
             ("uninstall", Some(uninstall_matches)) => {
-                println!(
-                    "Uninstalling {}...",
-                    uninstall_matches.value_of("target").unwrap()
-                );
+                let targets = match targets(uninstall_matches) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        println!("{}", e);
+                        drop(snapshot);
+                        drop(lock);
+                        std::process::exit(1);
+                    }
+                };
+
+                let mut options = libraries::UninstallOptions::new();
+                options.force(uninstall_matches.is_present("force"));
+                options.dry_run(dry_run);
+
+                if targets.len() == 1 {
+                    println!("Uninstalling {}...", targets[0]);
+
+                    match libraries::uninstall(
+                        config_file,
+                        uninstall_matches.is_present("global"),
+                        &targets[0],
+                        &options,
+                    ) {
+                        Ok(()) => {}
+                        Err(e) => println!("{}", e),
+                    }
+                } else {
+                    match batch::uninstall_all(
+                        config_file,
+                        uninstall_matches.is_present("global"),
+                        targets,
+                        &options,
+                    ) {
+                        Ok(true) => {}
+                        Ok(false) => success = false,
+                        Err(e) => println!("{}", e),
+                    }
+                }
+            }
 
-                match libraries::uninstall(
+            ("autoremove", Some(autoremove_matches)) => {
+                match dependencies::autoremove(
                     config_file,
-                    uninstall_matches.is_present("global"),
-                    uninstall_matches.value_of("target").unwrap(),
+                    autoremove_matches.is_present("global"),
+                    dry_run,
                 ) {
                     Ok(()) => {}
                     Err(e) => println!("{}", e),
@@ -103,13 +546,151 @@ fn main() {
                 };
             }
 
+            ("info", Some(info_matches)) => {
+                match libraries::info(config_file, info_matches.value_of("target").unwrap()) {
+                    Ok(()) => {}
+                    Err(e) => println!("{}", e),
+                }
+            }
+
             ("setup", Some(_)) => match config::setup(Some(config_file)) {
                 Ok(()) => {}
                 Err(e) => println!("{}", e),
             },
 
-            ("update", Some(_)) => {
+            ("update", Some(update_matches)) => {
                 println!("Updating library sources");
+
+                match libraries::update(
+                    config_file,
+                    update_matches.is_present("global"),
+                    update_matches.value_of("target"),
+                    dry_run,
+                    update_matches.is_present("force"),
+                ) {
+                    Ok(true) => {}
+                    Ok(false) => success = false,
+                    Err(e) => println!("{}", e),
+                }
+            }
+
+            ("rollback", Some(rollback_matches)) => {
+                match libraries::rollback(
+                    config_file,
+                    rollback_matches.is_present("global"),
+                    rollback_matches.value_of("target").unwrap(),
+                    rollback_matches.value_of("to"),
+                    dry_run,
+                ) {
+                    Ok(()) => {}
+                    Err(e) => println!("{}", e),
+                }
+            }
+
+            ("adopt", Some(_)) => match adopt::adopt(config_file, dry_run) {
+                Ok(()) => {}
+                Err(e) => println!("{}", e),
+            },
+
+            ("verify", Some(_)) => match libraries::verify(config_file) {
+                Ok(true) => {}
+                Ok(false) => success = false,
+                Err(e) => println!("{}", e),
+            },
+
+            ("doctor", Some(doctor_matches)) => {
+                match doctor::doctor(config_file, doctor_matches.is_present("fix")) {
+                    Ok(true) => {}
+                    Ok(false) => success = false,
+                    Err(e) => println!("{}", e),
+                }
+            }
+
+            ("list", Some(_)) => match libraries::list() {
+                Ok(()) => {}
+                Err(e) => println!("{}", e),
+            },
+
+            ("cache", Some(cache_matches)) => {
+                let result = match cache_matches.subcommand() {
+                    ("du", Some(_)) => cache::du(),
+                    ("clean", Some(_)) => cache::clean(dry_run),
+                    ("gc", Some(_)) => cache::gc(),
+                    _ => unreachable!(),
+                };
+                if let Err(e) = result {
+                    println!("{}", e);
+                }
+            }
+
+            ("export", Some(export_matches)) => {
+                match export::export(export_matches.value_of("file").unwrap()) {
+                    Ok(()) => {}
+                    Err(e) => println!("{}", e),
+                }
+            }
+
+            ("import", Some(import_matches)) => {
+                match export::import(
+                    config_file,
+                    import_matches.value_of("file").unwrap(),
+                    dry_run,
+                ) {
+                    Ok(true) => {}
+                    Ok(false) => success = false,
+                    Err(e) => println!("{}", e),
+                }
+            }
+
+            ("scan", Some(scan_matches)) => {
+                let mut options = libraries::InstallOptions::new();
+                options.dry_run(dry_run);
+                match scan::scan(
+                    config_file,
+                    scan_matches.value_of("project").unwrap(),
+                    scan_matches.is_present("install"),
+                    scan_matches.is_present("global"),
+                    &options,
+                ) {
+                    Ok(true) => {}
+                    Ok(false) => success = false,
+                    Err(e) => println!("{}", e),
+                }
+            }
+
+            ("diff", Some(diff_matches)) => {
+                match diff::diff(
+                    &config_file,
+                    diff_matches.value_of("library").unwrap(),
+                    diff_matches.value_of("from"),
+                    diff_matches.value_of("to"),
+                ) {
+                    Ok(()) => {}
+                    Err(e) => println!("{}", e),
+                }
+            }
+
+            ("mirror", Some(mirror_matches)) => {
+                match mirror::mirror(&config_file, mirror_matches.value_of("directory").unwrap()) {
+                    Ok(true) => {}
+                    Ok(false) => success = false,
+                    Err(e) => println!("{}", e),
+                }
+            }
+
+            ("history", Some(_)) => match history::history() {
+                Ok(()) => {}
+                Err(e) => println!("{}", e),
+            },
+
+            ("undo", Some(undo_matches)) => {
+                match undo_matches.value_of("count").unwrap().parse::<usize>() {
+                    Ok(count) => match history::undo(&config_file, count, dry_run) {
+                        Ok(()) => {}
+                        Err(e) => println!("{}", e),
+                    },
+                    Err(e) => println!("{}", e),
+                }
             }
 
             _ => unreachable!(),
@@ -126,4 +707,10 @@ fn main() {
             ),
         }
     }
+
+    // release the lock before exiting with an error
+    drop(lock);
+    if !success {
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,113 @@
+use crate::config::Config;
+use crate::git;
+use crate::libraries::{get_libraries, Library, Source};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::{error, fs};
+
+// host and path of a url, as in github.com/user/repo.git for https://github.com/user/repo.git
+// or git@github.com:user/repo.git
+fn host_path(url: &str) -> String {
+    let rest = match url.find("://") {
+        Some(index) => &url[index + 3..],
+        None => url,
+    };
+    let rest = match (rest.find('@'), rest.find('/')) {
+        (Some(at), Some(slash)) if at < slash => &rest[at + 1..],
+        (Some(at), None) => &rest[at + 1..],
+        _ => rest,
+    };
+    if url.contains("://") {
+        rest.to_owned()
+    } else {
+        rest.replacen(':', "/", 1)
+    }
+}
+
+fn is_local(url: &str) -> bool {
+    url.starts_with("file://")
+        || url.starts_with('/')
+        || url.starts_with('.')
+        || url.starts_with('~')
+}
+
+// url prefix up to and including the host, the part a rewrite rule replaces
+fn host_prefix(url: &str) -> &str {
+    let end = match url.find("://") {
+        Some(index) => url[index + 3..].find('/').map(|x| index + 3 + x),
+        None => url.find(':'),
+    };
+    match end {
+        Some(end) => &url[..=end],
+        None => url,
+    }
+}
+
+/// Create or update bare mirrors of the library index and of every remote git library in it
+/// below directory, laid out by host and path, and print the url rewrite rules for
+/// config.ron that point to them. Returns whether every repository was mirrored.
+pub fn mirror(config: &Config, directory: &str) -> Result<bool, Box<dyn error::Error>> {
+    fs::create_dir_all(directory)?;
+    let directory = fs::canonicalize(directory)?;
+
+    let mut urls: Vec<String> = Vec::new();
+    if let Some(sources) = Path::new(&config.libraries).parent() {
+        match git::remote_url(sources) {
+            Some(url) if !is_local(&url) => urls.push(url),
+            _ => {}
+        }
+    }
+
+    let index = get_libraries(config.libraries.clone())?;
+    let mut libraries: Vec<&Library> = index.lib_map.values().collect();
+    libraries.sort_by(|a, b| a.name.cmp(&b.name));
+    for library in libraries {
+        if library.source == Source::Git && library.local_path().is_none() {
+            urls.push(library.url.clone());
+        } else {
+            println!(
+                "{} is not a remote git repository, not mirroring it.",
+                library.name
+            );
+        }
+    }
+    urls.dedup();
+
+    let mut failed = Vec::new();
+    let mut rules = BTreeSet::new();
+    for url in urls.iter() {
+        let destination: PathBuf = directory.join(host_path(url));
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match git::mirror(url, &destination) {
+            Ok(()) => {
+                let prefix = host_prefix(url);
+                let host = host_path(prefix);
+                rules.insert((
+                    prefix.to_owned(),
+                    format!(
+                        "file://{}/",
+                        directory.join(host.trim_end_matches('/')).display()
+                    ),
+                ));
+            }
+            Err(e) => {
+                println!("{}: {}", url, e.message());
+                failed.push(url.clone());
+            }
+        }
+    }
+
+    if !rules.is_empty() {
+        println!("Add to url_rewrites in config.ron to use the mirror:");
+        for (prefix, replacement) in rules.iter() {
+            println!("  \"{}\": \"{}\",", prefix, replacement);
+        }
+    }
+    if !failed.is_empty() {
+        println!("Failed to mirror: {}", failed.join(", "));
+    }
+
+    Ok(failed.is_empty())
+}
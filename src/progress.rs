@@ -0,0 +1,53 @@
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+/// One status line per concurrent transfer, redrawn together in place.
+pub struct ProgressBoard {
+    state: Mutex<Board>,
+}
+
+struct Board {
+    lines: Vec<(String, String)>,
+    drawn: usize,
+}
+
+impl ProgressBoard {
+    pub fn new() -> ProgressBoard {
+        ProgressBoard {
+            state: Mutex::new(Board {
+                lines: Vec::new(),
+                drawn: 0,
+            }),
+        }
+    }
+
+    /// Add a line for a transfer, returns the line to pass to set.
+    pub fn add(&self, label: &str) -> usize {
+        let mut board = self.state.lock().unwrap();
+        board.lines.push((label.to_owned(), "waiting".to_owned()));
+        draw(&mut board);
+        board.lines.len() - 1
+    }
+
+    pub fn set(&self, line: usize, text: &str) {
+        let mut board = self.state.lock().unwrap();
+        if board.lines[line].1 != text {
+            board.lines[line].1 = text.to_owned();
+            draw(&mut board);
+        }
+    }
+}
+
+// move the cursor back over the previously drawn lines and print every line again
+fn draw(board: &mut Board) {
+    let mut stdout = io::stdout();
+    if board.drawn > 0 {
+        let _ = write!(stdout, "\x1b[{}A", board.drawn);
+    }
+    let width = board.lines.iter().map(|(x, _)| x.len()).max().unwrap_or(0);
+    for (label, text) in board.lines.iter() {
+        let _ = writeln!(stdout, "\r\x1b[2K{:width$}  {}", label, text, width = width);
+    }
+    let _ = stdout.flush();
+    board.drawn = board.lines.len();
+}
@@ -0,0 +1,283 @@
+use crate::batch;
+use crate::cache;
+use crate::checksum;
+use crate::config::Config;
+use crate::lib_table::LibTable;
+use crate::libraries::{get_libraries, InstallOptions, Library};
+use crate::sexpr::{self, Sexpr};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::{error, fs};
+
+// extensions of schematic and board files
+const PROJECT_EXTENSIONS: [&str; 3] = ["kicad_sch", "sch", "kicad_pcb"];
+
+// library nickname of a lib_id or footprint reference such as Device:R
+fn nickname(reference: &str) -> Option<String> {
+    match reference.find(':') {
+        Some(index) if index > 0 => Some(reference[..index].to_owned()),
+        _ => None,
+    }
+}
+
+// schematic and board files of a project directory, project file or single file
+fn project_files(project: &Path) -> Result<Vec<PathBuf>, Box<dyn error::Error>> {
+    let is_project_file = |path: &Path| {
+        PROJECT_EXTENSIONS
+            .iter()
+            .any(|x| path.extension().is_some_and(|e| e == *x))
+    };
+
+    if project.is_file() && is_project_file(project) {
+        return Ok(vec![project.to_path_buf()]);
+    }
+    let directory = if project.is_file() {
+        project.parent().unwrap_or_else(|| Path::new("."))
+    } else {
+        project
+    };
+
+    // KiCad keeps backups of the same files in <project>-backups
+    Ok(checksum::walk(directory)?
+        .into_iter()
+        .filter(|path| is_project_file(path))
+        .filter(|path| {
+            !path
+                .components()
+                .any(|x| x.as_os_str().to_string_lossy().ends_with("-backups"))
+        })
+        .collect())
+}
+
+/// Symbols and footprints a project uses, as Nickname:Name, with the files using each.
+#[derive(Default)]
+pub struct References {
+    pub symbols: BTreeMap<String, BTreeSet<PathBuf>>,
+    pub footprints: BTreeMap<String, BTreeSet<PathBuf>>,
+}
+
+impl References {
+    /// References of every schematic and board of a project, files that can't be read or
+    /// parsed are reported and skipped.
+    pub fn of_project(project: &Path) -> Result<References, Box<dyn error::Error>> {
+        let mut references = References::default();
+        for file in project_files(project)? {
+            if let Err(e) = references.add_file(&file) {
+                println!("{}: {}", file.display(), e);
+            }
+        }
+        Ok(references)
+    }
+
+    fn add(map: &mut BTreeMap<String, BTreeSet<PathBuf>>, reference: &str, path: &Path) {
+        if nickname(reference).is_some() {
+            map.entry(reference.to_owned())
+                .or_default()
+                .insert(path.to_path_buf());
+        }
+    }
+
+    fn add_file(&mut self, path: &Path) -> Result<(), Box<dyn error::Error>> {
+        let text = fs::read_to_string(path)?;
+
+        // KiCad 5 schematics are not s-expressions, components read
+        // L Device:R R1
+        // F 2 "Resistor_SMD:R_0603" H 0 0 50  0001 C CNN
+        if path.extension().is_some_and(|x| x == "sch") {
+            for line in text.lines() {
+                let mut fields = line.split_whitespace();
+                match (fields.next(), fields.next()) {
+                    (Some("L"), Some(lib_id)) => References::add(&mut self.symbols, lib_id, path),
+                    (Some("F"), Some("2")) => {
+                        let footprint = line.split('"').nth(1).unwrap_or_default();
+                        References::add(&mut self.footprints, footprint, path);
+                    }
+                    _ => {}
+                }
+            }
+            return Ok(());
+        }
+
+        let atom = |list: &Sexpr, index: usize| match list {
+            Sexpr::List(items) => items
+                .get(index)
+                .and_then(|x| x.atom())
+                .map(|x| x.to_owned()),
+            Sexpr::Atom(_) => None,
+        };
+        for root in sexpr::parse(&text)?.iter() {
+            for lib_id in root.find_all("lib_id") {
+                if let Some(lib_id) = atom(lib_id, 1) {
+                    References::add(&mut self.symbols, &lib_id, path);
+                }
+            }
+            for property in root.find_all("property") {
+                if atom(property, 1).as_deref() == Some("Footprint") {
+                    if let Some(footprint) = atom(property, 2) {
+                        References::add(&mut self.footprints, &footprint, path);
+                    }
+                }
+            }
+            // boards place footprints, called modules before KiCad 6
+            for key in ["footprint", "module"].iter() {
+                for footprint in root.find_all(key) {
+                    if let Some(footprint) = atom(footprint, 1) {
+                        References::add(&mut self.footprints, &footprint, path);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // nicknames of the libraries referenced in a map
+    fn nicknames(map: &BTreeMap<String, BTreeSet<PathBuf>>) -> BTreeSet<String> {
+        map.keys().filter_map(|x| nickname(x)).collect()
+    }
+}
+
+// symbol and footprint nicknames a library in the index provides, read from its local
+// source or clone, or the nicknames the index declares for it when it was never fetched,
+// None when it declares none
+fn provided(config: &Config, library: &Library) -> Option<(Vec<String>, Vec<String>)> {
+    let mut library = library.clone();
+    if let Some(prefix) = config.nickname_prefixes.get(&library.name) {
+        library.nickname_prefix = Some(prefix.clone());
+    }
+    if let Some(selection) = config.selections.get(&library.name) {
+        library.selection = selection.clone();
+    }
+
+    let root = library
+        .local_path()
+        .filter(|x| x.is_dir())
+        .or_else(|| cache::clone_path(&library.name));
+    if let Some(Ok((sym_files, fp_files))) = root.map(|x| library.provided_files(&x)) {
+        return Some((
+            sym_files.into_iter().map(|(_, x)| x).collect(),
+            fp_files.into_iter().map(|(_, x)| x).collect(),
+        ));
+    }
+
+    if library.nicknames.is_empty() {
+        return None;
+    }
+    let nicknames: Vec<String> = library.nicknames.values().cloned().collect();
+    Some((nicknames.clone(), nicknames))
+}
+
+/// Report the symbol and footprint libraries referenced by a project that are missing from
+/// the lib tables with the libraries in the index providing them, installing those when
+/// install is set. Returns whether every referenced library is available afterwards.
+pub fn scan(
+    config: Config,
+    project: &str,
+    install: bool,
+    global: bool,
+    options: &InstallOptions,
+) -> Result<bool, Box<dyn error::Error>> {
+    let files = project_files(Path::new(project))?;
+    if files.is_empty() {
+        println!("No schematic or board files found in {}", project);
+        return Ok(true);
+    }
+
+    for file in files.iter() {
+        println!("scanning: {}", file.display());
+    }
+    let references = References::of_project(Path::new(project))?;
+    let symbols = References::nicknames(&references.symbols);
+    let footprints = References::nicknames(&references.footprints);
+
+    let sym_lib_table = LibTable::load(&config.sym_lib_table, "sym_lib_table")?;
+    let fp_lib_table = LibTable::load(&config.fp_lib_table, "fp_lib_table")?;
+    let index = get_libraries(config.libraries.clone())?;
+    let mut libraries: Vec<&Library> = index.lib_map.values().collect();
+    libraries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut sym_providers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut fp_providers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut unresolved = Vec::new();
+    for library in libraries {
+        let (sym_nicknames, fp_nicknames) = match provided(&config, library) {
+            Some(x) => x,
+            None => {
+                unresolved.push(library.name.clone());
+                continue;
+            }
+        };
+        for nickname in sym_nicknames {
+            sym_providers
+                .entry(nickname)
+                .or_default()
+                .push(library.name.clone());
+        }
+        for nickname in fp_nicknames {
+            fp_providers
+                .entry(nickname)
+                .or_default()
+                .push(library.name.clone());
+        }
+    }
+
+    let mut targets: Vec<String> = Vec::new();
+    let mut unknown = Vec::new();
+    for (kind, nicknames, table, providers) in [
+        ("symbol", &symbols, &sym_lib_table, &sym_providers),
+        ("footprint", &footprints, &fp_lib_table, &fp_providers),
+    ]
+    .iter()
+    {
+        for nickname in nicknames.iter() {
+            if table.find(nickname).is_some() {
+                println!("  {} library {}: available", kind, nickname);
+                continue;
+            }
+            match providers.get(nickname) {
+                Some(names) => {
+                    println!(
+                        "  {} library {}: missing, provided by {}",
+                        kind,
+                        nickname,
+                        names.join(", ")
+                    );
+                    if !names.iter().any(|x| targets.contains(x)) {
+                        targets.push(names[0].clone());
+                    }
+                }
+                None => {
+                    println!(
+                        "  {} library {}: missing, no known library provides it",
+                        kind, nickname
+                    );
+                    unknown.push(nickname.clone());
+                }
+            }
+        }
+    }
+
+    if targets.is_empty() {
+        println!("No libraries to install.");
+    } else if !install {
+        println!("Libraries to install: {}", targets.join(" "));
+    }
+    if !unknown.is_empty() {
+        println!("Not provided by any library: {}", unknown.join(", "));
+    }
+    // the nicknames of a library are only known once it is fetched, unless the index
+    // declares them
+    if !unknown.is_empty() && !unresolved.is_empty() {
+        println!(
+            "Never fetched, these libraries may provide them: {}",
+            unresolved.join(", ")
+        );
+    }
+
+    let mut complete = unknown.is_empty();
+    if install && !targets.is_empty() {
+        complete &= batch::install_all(config, global, targets, options)?;
+    }
+
+    Ok(complete)
+}
@@ -0,0 +1,210 @@
+use std::{error, fmt};
+
+/// A node of a KiCad s-expression file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sexpr {
+    Atom(String),
+    List(Vec<Sexpr>),
+}
+
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+pub enum SexprError {
+    UnbalancedError,
+    UnterminatedStringError,
+}
+
+impl fmt::Display for SexprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SexprError::UnbalancedError => write!(f, "Unbalanced parentheses."),
+            SexprError::UnterminatedStringError => write!(f, "Unterminated string."),
+        }
+    }
+}
+
+impl error::Error for SexprError {
+    fn description(&self) -> &str {
+        match self {
+            SexprError::UnbalancedError => "Unbalanced parentheses.",
+            SexprError::UnterminatedStringError => "Unterminated string.",
+        }
+    }
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+impl Sexpr {
+    /// The atom value, if this is an atom.
+    pub fn atom(&self) -> Option<&str> {
+        match self {
+            Sexpr::Atom(x) => Some(&x[..]),
+            Sexpr::List(_) => None,
+        }
+    }
+
+    /// The head atom of a list, e.g. `lib` for `(lib (name x))`.
+    pub fn head(&self) -> Option<&str> {
+        match self {
+            Sexpr::List(items) => items.first().and_then(|x| x.atom()),
+            Sexpr::Atom(_) => None,
+        }
+    }
+
+    /// The value of a `(key value)` child of a list.
+    pub fn value(&self, key: &str) -> Option<&str> {
+        match self {
+            Sexpr::List(items) => {
+                items
+                    .iter()
+                    .find(|x| x.head() == Some(key))
+                    .and_then(|x| match x {
+                        Sexpr::List(kv) => kv.get(1).and_then(|v| v.atom()),
+                        Sexpr::Atom(_) => None,
+                    })
+            }
+            Sexpr::Atom(_) => None,
+        }
+    }
+
+    /// Every list below this one, at any depth, with the given head atom.
+    pub fn find_all(&self, key: &str) -> Vec<&Sexpr> {
+        let mut found = Vec::new();
+        if let Sexpr::List(items) = self {
+            for item in items.iter() {
+                if item.head() == Some(key) {
+                    found.push(item);
+                }
+                found.append(&mut item.find_all(key));
+            }
+        }
+        found
+    }
+}
+
+impl fmt::Display for Sexpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Sexpr::Atom(x) => {
+                let quote =
+                    x.is_empty() || x.chars().any(|c| c.is_whitespace() || "()\"\\".contains(c));
+                if !quote {
+                    return write!(f, "{}", x);
+                }
+
+                write!(f, "\"")?;
+                for c in x.chars() {
+                    match c {
+                        '"' | '\\' => write!(f, "\\{}", c)?,
+                        '\n' => write!(f, "\\n")?,
+                        _ => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Sexpr::List(items) => {
+                write!(f, "(")?;
+                for (i, item) in items.iter().enumerate() {
+                    // KiCad separates atoms and the first child list with a space
+                    if i == 1 || (i > 1 && item.atom().is_some()) {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// Parse every top level expression of a KiCad s-expression file.
+pub fn parse(text: &str) -> Result<Vec<Sexpr>, SexprError> {
+    let mut stack: Vec<Vec<Sexpr>> = vec![Vec::new()];
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => stack.push(Vec::new()),
+            ')' => {
+                let list = stack.pop().ok_or(SexprError::UnbalancedError)?;
+                stack
+                    .last_mut()
+                    .ok_or(SexprError::UnbalancedError)?
+                    .push(Sexpr::List(list));
+            }
+            '"' => {
+                let mut atom = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => atom.push('\n'),
+                            Some(x) => atom.push(x),
+                            None => return Err(SexprError::UnterminatedStringError),
+                        },
+                        Some(x) => atom.push(x),
+                        None => return Err(SexprError::UnterminatedStringError),
+                    }
+                }
+                stack.last_mut().unwrap().push(Sexpr::Atom(atom));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut atom = c.to_string();
+                while let Some(&x) = chars.peek() {
+                    if x.is_whitespace() || x == '(' || x == ')' || x == '"' {
+                        break;
+                    }
+                    atom.push(x);
+                    chars.next();
+                }
+                stack.last_mut().unwrap().push(Sexpr::Atom(atom));
+            }
+        }
+    }
+
+    if stack.len() != 1 {
+        return Err(SexprError::UnbalancedError);
+    }
+
+    Ok(stack.pop().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Sexpr};
+
+    #[test]
+    fn display_round_trips_parse() {
+        let text = r#"(kicad_symbol_lib (version 20231120) (generator "kicad symbol editor")
+  (symbol "R" (pin_numbers hide)
+    (property "Value" "R" (at 0 0 90))
+    (property "Description" "Resistor, \"small\"\nsecond line")
+    (pin passive line (at 0 3.81 270) (length 1.27) (name "~" (effects)) (number "1"))
+  )
+)"#;
+        let parsed = parse(text).unwrap();
+        let written: Vec<String> = parsed.iter().map(|x| x.to_string()).collect();
+        assert_eq!(parse(&written.join("\n")).unwrap(), parsed);
+    }
+
+    #[test]
+    fn atoms_are_quoted_when_needed() {
+        let list = Sexpr::List(vec![
+            Sexpr::Atom("descr".to_owned()),
+            Sexpr::Atom("".to_owned()),
+            Sexpr::Atom("two words".to_owned()),
+            Sexpr::Atom("a\"b".to_owned()),
+            Sexpr::List(vec![Sexpr::Atom("hidden".to_owned())]),
+        ]);
+        assert_eq!(list.to_string(), r#"(descr "" "two words" "a\"b"(hidden))"#);
+    }
+
+    #[test]
+    fn unbalanced_and_unterminated_input_fails() {
+        assert!(parse("(lib (name x)").is_err());
+        assert!(parse("(lib))").is_err());
+        assert!(parse("(lib (name \"x))").is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use crate::cache;
+use crate::config::Config;
+use crate::git::{self, clone};
+use crate::libraries::{remove_dir, Library, Source};
+use crate::progress::ProgressBoard;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{fs, io, thread};
+
+// whether a Staging guard is alive in this process
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+fn staging_path() -> PathBuf {
+    PathBuf::from(format!("{}/.kibrarian/staging", env!("HOME")))
+}
+
+/// Directory a library is cloned to ahead of its installation.
+pub fn staged_path(name: &str) -> PathBuf {
+    staging_path().join(name)
+}
+
+/// Keeps ~/.kibrarian/staging while libraries are installed, the outermost guard removes
+/// it when dropped.
+pub struct Staging {
+    owner: bool,
+}
+
+impl Staging {
+    pub fn begin() -> io::Result<Staging> {
+        if ACTIVE.swap(true, Ordering::SeqCst) {
+            return Ok(Staging { owner: false });
+        }
+
+        // clones left behind by an interrupted run may be of another revision, they are
+        // fetched again from the cache
+        cache_clones()?;
+        remove_dir(staging_path())?;
+        fs::create_dir_all(staging_path())?;
+        Ok(Staging { owner: true })
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        if self.owner {
+            let _ = cache_clones();
+            let _ = remove_dir(staging_path());
+            ACTIVE.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+// move clones no installation used into the cache, unless it has a clone of the library
+fn cache_clones() -> io::Result<()> {
+    let entries = match fs::read_dir(staging_path()) {
+        Ok(x) => x,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+        let cached = cache::cached_path(&path.file_name().unwrap_or_default().to_string_lossy());
+        if path.join(".git").is_dir() && !cached.exists() {
+            if let Some(parent) = cached.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&path, &cached)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Clone the remote git libraries concurrently into the staging directory, at most
+/// config.jobs at a time, showing one progress line per library. Installed and cached clones
+/// are fetched instead, offline nothing is staged. Failed clones are left for the installation to retry
+/// and report.
+pub fn stage(config: &Config, libraries: &[Library]) {
+    if config.offline {
+        return;
+    }
+
+    let queue: VecDeque<Library> = libraries
+        .iter()
+        .filter(|x| x.source == Source::Git && x.local_path().is_none())
+        .filter(|x| !staged_path(&x.name).exists())
+        .cloned()
+        .collect();
+
+    // a single clone reports its progress as usual during installation
+    if queue.len() < 2 {
+        return;
+    }
+
+    println!("Cloning {} libraries...", queue.len());
+    let board = Arc::new(ProgressBoard::new());
+    let queue: VecDeque<(Library, usize)> = queue
+        .into_iter()
+        .map(|mut library| {
+            let line = board.add(&library.name);
+            library.url = config.rewrite_url(&library.url);
+            (library, line)
+        })
+        .collect();
+    let jobs = config.jobs.max(1).min(queue.len());
+    let queue = Arc::new(Mutex::new(queue));
+
+    let workers: Vec<_> = (0..jobs)
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let board = Arc::clone(&board);
+            let full_fallback = config.full_clone_fallback;
+            thread::spawn(move || loop {
+                let next = queue.lock().unwrap().pop_front();
+                let (library, line) = match next {
+                    Some(x) => x,
+                    None => break,
+                };
+
+                let destination = staged_path(&library.name);
+                let mut options = library.clone_options(full_fallback);
+                options.progress(Arc::clone(&board), line);
+                let installation = cache::installation_path(&library.name);
+                let result = if installation.join(".git").is_dir() {
+                    // an installed clone is fetched in place, it moves to the cache only when
+                    // its library is reinstalled
+                    git::prefetch(&installation, &library.url, &options)
+                        .map_err(|e| e.message().to_owned())
+                } else {
+                    match cache::restore(&library.name, &library.url, &destination, &options, false)
+                    {
+                        Ok(true) => Ok(()),
+                        Ok(false) => clone(
+                            &library.url,
+                            destination.to_string_lossy().into_owned(),
+                            &options,
+                        )
+                        .map_err(|e| e.message().to_owned()),
+                        Err(e) => Err(e.to_string()),
+                    }
+                };
+                match result {
+                    Ok(()) => board.set(line, "done"),
+                    Err(e) => {
+                        let _ = remove_dir(&destination);
+                        board.set(line, &format!("failed: {}", e));
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+}